@@ -0,0 +1,378 @@
+use std::path::{Component, Path, PathBuf};
+
+use actix_web::{error::ErrorForbidden, web, Error};
+
+use crate::auth::Identity;
+use crate::dir::Dir;
+use crate::state::{AppState, Library};
+
+// Rights on a path, each level includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    None,
+    Read,
+    Write,
+    Admin,
+}
+
+impl std::str::FromStr for Access {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "read" => Ok(Self::Read),
+            "write" => Ok(Self::Write),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("Unknown access \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    // "*" matches everyone, including anonymous requests
+    Anyone,
+    // "@name" matches members of a group
+    Group(String),
+    User(String),
+}
+
+impl Subject {
+    fn parse(s: &str) -> Self {
+        if s == "*" {
+            Self::Anyone
+        } else if let Some(group) = s.strip_prefix('@') {
+            Self::Group(group.to_string())
+        } else {
+            Self::User(s.to_string())
+        }
+    }
+
+    fn matches(&self, identity: Option<&Identity>) -> bool {
+        match (self, identity) {
+            (Self::Anyone, _) => true,
+            (Self::User(name), Some(identity)) => &identity.name == name,
+            (Self::Group(group), Some(identity)) => identity.groups.contains(group),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub subject: Subject,
+    pub prefix: PathBuf,
+    pub access: Access,
+}
+
+// Access control list mapping users and groups to path prefixes
+// One rule per line: "<user|@group|*> <path prefix> <none|read|write|admin>"
+// The rules with the longest matching prefix decide the access to a path
+#[derive(Debug, Default)]
+pub struct Acl {
+    rules: Vec<Rule>,
+}
+
+// Normalize a prefix to a relative path ("/photos/" -> "photos")
+fn clean_prefix(prefix: &str) -> PathBuf {
+    Path::new(prefix)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Acl {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 3 {
+                return Err(format!(
+                    "Line {}: expected \"<subject> <path> <access>\"",
+                    i + 1
+                ));
+            }
+            rules.push(Rule {
+                subject: Subject::parse(parts[0]),
+                prefix: clean_prefix(parts[1]),
+                access: parts[2]
+                    .parse()
+                    .map_err(|e| format!("Line {}: {}", i + 1, e))?,
+            });
+        }
+
+        Ok(Self { rules })
+    }
+
+    // Access to a path relative to the root
+    pub fn access(&self, identity: Option<&Identity>, path: &Path) -> Access {
+        let matching = self
+            .rules
+            .iter()
+            .filter(|r| path.starts_with(&r.prefix) && r.subject.matches(identity));
+
+        // Most specific prefix wins, the highest access if several rules share it
+        let depth = matching
+            .clone()
+            .map(|r| r.prefix.components().count())
+            .max();
+        matching
+            .filter(|r| Some(r.prefix.components().count()) == depth)
+            .map(|r| r.access)
+            .max()
+            .unwrap_or(Access::None)
+    }
+
+    // Access to a path and everything under it: the lowest one given by the rules
+    // inside it, since moving or deleting a folder affects all of its content
+    pub fn access_within(&self, identity: Option<&Identity>, path: &Path) -> Access {
+        self.rules
+            .iter()
+            .filter(|r| r.prefix.starts_with(path))
+            .map(|r| self.access(identity, &r.prefix))
+            .fold(self.access(identity, path), Access::min)
+    }
+
+    // Paths of the rules as found in the libraries they fall in, given by name and root
+    pub fn rule_paths(&self, roots: &[(&str, &Path)]) -> Vec<PathBuf> {
+        let mut paths = vec![];
+        for rule in &self.rules {
+            for (name, root) in roots {
+                if let Ok(relative) = rule.prefix.strip_prefix(name) {
+                    paths.push(root.join(relative));
+                }
+            }
+        }
+        paths
+    }
+
+    // Folders can be listed when readable, or when they lead to a readable subfolder
+    pub fn can_list(&self, identity: Option<&Identity>, path: &Path) -> bool {
+        self.access(identity, path) >= Access::Read
+            || self.rules.iter().any(|r| {
+                r.access >= Access::Read
+                    && r.prefix.starts_with(path)
+                    && r.subject.matches(identity)
+                    && self.access(identity, &r.prefix) >= Access::Read
+            })
+    }
+}
+
+// Path as used by rules: as seen by clients, under the library it was requested through
+// (the same folder can be in several libraries when their roots are nested)
+fn relative(library: &Library, path: &Path) -> PathBuf {
+    library
+        .client_path(path)
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// Access to a path of a library; everyone has full access without an ACL
+pub fn access(
+    state: &AppState,
+    identity: Option<&Identity>,
+    library: &Library,
+    path: &Path,
+) -> Access {
+    match &state.acl {
+        Some(acl) => acl.access(identity, &relative(library, path)),
+        None => Access::Admin,
+    }
+}
+
+pub fn access_within(
+    state: &AppState,
+    identity: Option<&Identity>,
+    library: &Library,
+    path: &Path,
+) -> Access {
+    match &state.acl {
+        Some(acl) => acl.access_within(identity, &relative(library, path)),
+        None => Access::Admin,
+    }
+}
+
+pub fn can_list(
+    state: &AppState,
+    identity: Option<&Identity>,
+    library: &Library,
+    path: &Path,
+) -> bool {
+    match &state.acl {
+        Some(acl) => acl.can_list(identity, &relative(library, path)),
+        None => true,
+    }
+}
+
+pub fn require(
+    state: &AppState,
+    identity: Option<&Identity>,
+    library: &Library,
+    path: &Path,
+    needed: Access,
+) -> Result<(), Error> {
+    if access(state, identity, library, path) >= needed {
+        Ok(())
+    } else {
+        Err(ErrorForbidden("Access denied"))
+    }
+}
+
+// What a user can see of a library, for scans running in the background: folders that
+// can be listed (they may lead to readable ones) and readable files
+// Scans only walk what the user can see, so their progress gives nothing else away
+pub fn scan_filter(
+    state: &web::Data<AppState>,
+    identity: Option<&Identity>,
+    library: &Library,
+) -> impl Fn(&Path, bool) -> bool + Send + 'static {
+    let state = state.clone();
+    let identity = identity.cloned();
+    let name = library.name.to_owned();
+    move |path: &Path, is_dir: bool| {
+        let library = state.library(&name).expect("Resolved library");
+        if is_dir {
+            can_list(&state, identity.as_ref(), library, path)
+        } else {
+            access(&state, identity.as_ref(), library, path) >= Access::Read
+        }
+    }
+}
+
+// Whose a background scan is: everyone's without an ACL, otherwise the user's alone
+pub fn scan_owner(state: &AppState, identity: Option<&Identity>) -> Option<String> {
+    state.acl.as_ref().and(identity).map(|i| i.name.to_owned())
+}
+
+// Remove entries (and thumbnails) from a listing that the user can not read
+// Entry paths are already as seen by clients (see Library::client_path)
+pub fn filter_dir(state: &AppState, dir: &mut Dir, identity: Option<&Identity>) {
+    let acl = match &state.acl {
+        Some(acl) => acl,
+        None => return,
+    };
+
+    dir.folders.retain(|f| acl.can_list(identity, &f.path));
+    dir.files
+        .retain(|f| acl.access(identity, &f.path) >= Access::Read);
+    for entry in dir.folders.iter_mut().chain(dir.files.iter_mut()) {
+        if let Some(thumb) = &entry.thumb {
+            if acl.access(identity, thumb) < Access::Read {
+                entry.thumb = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn user(name: &str, groups: &[&str]) -> Identity {
+        Identity {
+            name: name.to_string(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let acl = Acl::parse("# comment\n\n* / read\n@team /photos/team/ write\n").unwrap();
+        assert_eq!(acl.rules.len(), 2);
+        assert_eq!(acl.rules[0].prefix, PathBuf::from(""));
+        assert_eq!(
+            acl.rules[1],
+            Rule {
+                subject: Subject::Group(String::from("team")),
+                prefix: PathBuf::from("photos/team"),
+                access: Access::Write,
+            }
+        );
+
+        assert!(Acl::parse("* / everything").is_err());
+        assert!(Acl::parse("* read").is_err());
+    }
+
+    #[test]
+    fn test_access() {
+        let acl = Acl::parse(
+            "* / read\n\
+             * private none\n\
+             @team private/team write\n\
+             alice private admin\n",
+        )
+        .unwrap();
+        let alice = user("alice", &[]);
+        let bob = user("bob", &["team"]);
+
+        assert_eq!(acl.access(None, Path::new("imgs")), Access::Read);
+        assert_eq!(acl.access(None, Path::new("private")), Access::None);
+        assert_eq!(
+            acl.access(Some(&bob), Path::new("private/a.jpg")),
+            Access::None
+        );
+        assert_eq!(
+            acl.access(Some(&bob), Path::new("private/team/a.jpg")),
+            Access::Write
+        );
+        assert_eq!(
+            acl.access(Some(&alice), Path::new("private/team")),
+            Access::Admin
+        );
+        assert_eq!(
+            acl.access(Some(&alice), Path::new("private/other")),
+            Access::Admin
+        );
+
+        // "private/teams" is not inside "private/team"
+        assert_eq!(
+            acl.access(Some(&bob), Path::new("private/teams")),
+            Access::None
+        );
+    }
+
+    #[test]
+    fn test_access_within() {
+        let acl = Acl::parse(
+            "* docs write
+* docs/hr none
+@hr docs/hr write
+",
+        )
+        .unwrap();
+        let bob = user("bob", &["hr"]);
+
+        assert_eq!(acl.access(None, Path::new("docs")), Access::Write);
+        assert_eq!(acl.access_within(None, Path::new("docs")), Access::None);
+        assert_eq!(acl.access_within(None, Path::new("docs/a")), Access::Write);
+        assert_eq!(
+            acl.access_within(Some(&bob), Path::new("docs")),
+            Access::Write
+        );
+    }
+
+    #[test]
+    fn test_can_list() {
+        let acl = Acl::parse("@team photos/team read\n").unwrap();
+        let bob = user("bob", &["team"]);
+
+        assert!(acl.can_list(Some(&bob), Path::new("")));
+        assert!(acl.can_list(Some(&bob), Path::new("photos")));
+        assert!(acl.can_list(Some(&bob), Path::new("photos/team/2021")));
+        assert!(!acl.can_list(Some(&bob), Path::new("photos/other")));
+        assert!(!acl.can_list(None, Path::new("photos")));
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use flate2::read::GzDecoder;
+
+use crate::dir::{encode_path, Dir, DirError, FileEntry};
+use crate::media::FileStat;
+
+// Archives are browsable as virtual folders,
+// eg "ext/photos.zip/inner/file.jpg"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    // Comic book archives (.cbr) are nominally RAR,
+    // but are often zip or tar files with the extension changed
+    Rar,
+}
+
+pub const PAGE_TYPES: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "avif"];
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") || name.ends_with(".cbz") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") || name.ends_with(".cbt") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".cbr") {
+            Some(Self::Rar)
+        } else {
+            None
+        }
+    }
+
+    // Determine the kind of archive to read, checking the contents
+    // of files that claim to be RAR since only zip and tar can be read
+    pub fn open(path: &Path) -> Result<Self, DirError> {
+        match Self::from_path(path) {
+            Some(Self::Rar) => {
+                let mut magic = [0u8; 262];
+                let read = File::open(path)?.read(&mut magic)?;
+                if read >= 4 && magic.starts_with(b"PK\x03\x04") {
+                    Ok(Self::Zip)
+                } else if read >= 262 && &magic[257..262] == b"ustar" {
+                    Ok(Self::Tar)
+                } else {
+                    Err(DirError::Archive(String::from(
+                        "RAR archives are not supported",
+                    )))
+                }
+            }
+            Some(kind) => Ok(kind),
+            None => Err(DirError::NotFound),
+        }
+    }
+}
+
+// Single file or folder stored in an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub path: PathBuf,
+    pub size: u64,
+    pub date: u64,
+    pub is_dir: bool,
+}
+
+impl From<zip::result::ZipError> for DirError {
+    fn from(err: zip::result::ZipError) -> Self {
+        DirError::Archive(err.to_string())
+    }
+}
+
+// Member paths must stay inside the archive
+fn clean_path(path: &Path) -> Option<PathBuf> {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => clean.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if clean.as_os_str().is_empty() {
+        None
+    } else {
+        Some(clean)
+    }
+}
+
+fn zip_date(date: zip::DateTime) -> u64 {
+    NaiveDate::from_ymd_opt(date.year().into(), date.month().into(), date.day().into())
+        .and_then(|d| {
+            d.and_hms_opt(
+                date.hour().into(),
+                date.minute().into(),
+                date.second().into(),
+            )
+        })
+        .map(|dt| Utc.from_utc_datetime(&dt).timestamp().max(0) as u64)
+        .unwrap_or(0)
+}
+
+fn tar_reader(archive: &Path, kind: ArchiveKind) -> Result<tar::Archive<Box<dyn Read>>, DirError> {
+    let file = File::open(archive)?;
+    let reader: Box<dyn Read> = match kind {
+        ArchiveKind::TarGz => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+
+    Ok(tar::Archive::new(reader))
+}
+
+// List every member of an archive
+pub fn members(archive: &Path, kind: ArchiveKind) -> Result<Vec<Member>, DirError> {
+    let mut members = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            for i in 0..zip.len() {
+                let file = zip.by_index(i)?;
+                if let Some(path) = file.enclosed_name().and_then(clean_path) {
+                    members.push(Member {
+                        path,
+                        size: file.size(),
+                        date: zip_date(file.last_modified()),
+                        is_dir: file.is_dir(),
+                    });
+                }
+            }
+        }
+        ArchiveKind::Rar => return Err(DirError::Archive(String::from("Unknown archive"))),
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut tar = tar_reader(archive, kind)?;
+            for entry in tar.entries()? {
+                let entry = entry?;
+                let header = entry.header();
+                if let Some(path) = clean_path(&entry.path()?) {
+                    members.push(Member {
+                        path,
+                        size: entry.size(),
+                        date: header.mtime().unwrap_or(0),
+                        is_dir: header.entry_type().is_dir(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(members)
+}
+
+// Find the archive (if any) a path points into,
+// returning the archive file and the path inside of it
+pub fn split_path(root: &Path, path: &Path) -> Option<(PathBuf, PathBuf)> {
+    for ancestor in path.ancestors() {
+        if ancestor == root {
+            break;
+        }
+        if ArchiveKind::from_path(ancestor).is_some() && ancestor.is_file() {
+            let inner = path.strip_prefix(ancestor).ok()?.to_path_buf();
+            return Some((ancestor.to_path_buf(), inner));
+        }
+    }
+
+    None
+}
+
+// Load the members directly inside `inner` (archive root when empty) into Dir struct
+pub fn get_archive_dir(root: &Path, archive: &Path, inner: &Path) -> Result<Dir, DirError> {
+    let kind = ArchiveKind::open(archive)?;
+    let mut base = encode_path(
+        archive
+            .strip_prefix(root)
+            .map_err(|_| DirError::OutsideRoot)?,
+    )?;
+    // Joining an empty path would add a trailing "/"
+    if !inner.as_os_str().is_empty() {
+        base.push(inner);
+    }
+
+    let mut files = Vec::new();
+    // Folders are not always stored as members of their own,
+    // so they are collected from the paths of their contents
+    let mut folders: BTreeMap<String, u64> = BTreeMap::new();
+    let mut found = inner.as_os_str().is_empty();
+    for member in members(archive, kind)? {
+        let rest = match member.path.strip_prefix(inner) {
+            Ok(rest) => rest,
+            Err(_) => continue,
+        };
+
+        let mut components = rest.components();
+        let name = match components.next() {
+            Some(name) => name.as_os_str().to_string_lossy().to_string(),
+            None => {
+                found = found || member.is_dir;
+                continue;
+            }
+        };
+        found = true;
+
+        if member.is_dir || components.next().is_some() {
+            let date = folders.entry(name).or_insert(0);
+            *date = (*date).max(member.date);
+        } else {
+            files.push(FileEntry::new(
+                name.to_owned(),
+                base.join(&name),
+                member.size,
+                member.date,
+            ));
+        }
+    }
+
+    if !found {
+        return Err(DirError::NotFound);
+    }
+
+    let mut page = Dir::new(base.display().to_string());
+    for (name, date) in folders {
+        page.folders
+            .push(FileEntry::new(name.to_owned(), base.join(&name), 0, date));
+    }
+    page.files = files;
+
+    page.sort();
+    Ok(page)
+}
+
+// Details of a single member of an archive, or of a folder inside it
+pub fn stat_member(root: &Path, archive: &Path, inner: &Path) -> Result<FileStat, DirError> {
+    let kind = ArchiveKind::open(archive)?;
+    let path = encode_path(
+        archive
+            .strip_prefix(root)
+            .map_err(|_| DirError::OutsideRoot)?,
+    )?
+    .join(inner);
+    let name = inner
+        .file_name()
+        .ok_or(DirError::NotFound)?
+        .to_string_lossy()
+        .to_string();
+
+    // Folders are not always stored as members of their own (see get_archive_dir)
+    let mut folder = None;
+    for member in members(archive, kind)? {
+        if member.path == inner && !member.is_dir {
+            let entry = FileEntry::new(name, path, member.size, member.date);
+            return Ok(FileStat::guess(entry, false));
+        } else if member.path.starts_with(inner) {
+            folder = Some(folder.unwrap_or(0).max(member.date));
+        }
+    }
+
+    folder
+        .map(|date| FileStat::guess(FileEntry::new(name, path, 0, date), true))
+        .ok_or(DirError::NotFound)
+}
+
+// List every image in an archive, in reading order
+// Pages are sorted by their full path with the same
+// number-aware ordering used for folder listings
+pub fn get_pages(root: &Path, archive: &Path) -> Result<Vec<FileEntry>, DirError> {
+    let kind = ArchiveKind::open(archive)?;
+    let base = encode_path(
+        archive
+            .strip_prefix(root)
+            .map_err(|_| DirError::OutsideRoot)?,
+    )?;
+
+    let mut pages: Vec<FileEntry> = members(archive, kind)?
+        .into_iter()
+        .filter(|m| {
+            !m.is_dir
+                && m.path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| PAGE_TYPES.contains(&e.to_lowercase().as_str()))
+                    .unwrap_or(false)
+        })
+        .map(|m| {
+            let name = m.path.to_string_lossy().to_string();
+            FileEntry::new(name, base.join(&m.path), m.size, m.date)
+        })
+        .collect();
+
+    pages.sort_by(|a, b| {
+        alphanumeric_sort::compare_str(a.name.to_lowercase(), b.name.to_lowercase())
+    });
+    Ok(pages)
+}
+
+// Find the file `inner` of an archive and pass its size and content to `read`,
+// in a single pass over the archive; Ok(false) when it isn't a file of the archive
+pub fn read_member<F>(archive: &Path, inner: &Path, mut read: F) -> Result<bool, DirError>
+where
+    F: FnMut(u64, &mut dyn Read) -> std::io::Result<()>,
+{
+    match ArchiveKind::open(archive)? {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            let index = (0..zip.len()).find(|&i| {
+                zip.by_index(i)
+                    .ok()
+                    .and_then(|f| f.enclosed_name().and_then(clean_path))
+                    .as_deref()
+                    == Some(inner)
+            });
+            let mut file = match index {
+                Some(index) => zip.by_index(index)?,
+                None => return Ok(false),
+            };
+            if file.is_dir() {
+                return Ok(false);
+            }
+            read(file.size(), &mut file)?;
+            Ok(true)
+        }
+        ArchiveKind::Rar => Err(DirError::Archive(String::from("Unknown archive"))),
+        kind => {
+            let mut tar = tar_reader(archive, kind)?;
+            for entry in tar.entries()? {
+                let mut entry = entry?;
+                let path = clean_path(&entry.path()?);
+                if path.as_deref() == Some(inner) && !entry.header().entry_type().is_dir() {
+                    read(entry.size(), &mut entry)?;
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::media::Kind;
+
+    const ROOT: &str = "./test-fixtures/";
+
+    #[test]
+    fn test_archive_kind() {
+        let kind = |p: &str| ArchiveKind::from_path(Path::new(p));
+        assert_eq!(kind("a/photos.ZIP"), Some(ArchiveKind::Zip));
+        assert_eq!(kind("photos.tar"), Some(ArchiveKind::Tar));
+        assert_eq!(kind("photos.tar.gz"), Some(ArchiveKind::TarGz));
+        assert_eq!(kind("photos.tgz"), Some(ArchiveKind::TarGz));
+        assert_eq!(kind("comic.cbz"), Some(ArchiveKind::Zip));
+        assert_eq!(kind("comic.cbr"), Some(ArchiveKind::Rar));
+        assert_eq!(kind("photos.gz"), None);
+        assert_eq!(kind("photos.jpg"), None);
+    }
+
+    #[test]
+    fn test_split_path() {
+        let root = Path::new(ROOT);
+        let path = root.join("archive/album.zip/inner/b.txt");
+        let (archive, inner) = split_path(root, &path).unwrap();
+        assert_eq!(archive, root.join("archive/album.zip"));
+        assert_eq!(inner, PathBuf::from("inner/b.txt"));
+
+        assert!(split_path(root, &root.join("imgs/flower.jpg")).is_none());
+    }
+
+    #[test]
+    fn test_get_archive_dir() {
+        for name in &["album.zip", "album.tar.gz"] {
+            let archive = Path::new(ROOT).join("archive").join(name);
+
+            let root = get_archive_dir(Path::new(ROOT), &archive, Path::new("")).unwrap();
+            assert_eq!(root.title, format!("archive/{}", name));
+            assert_eq!(root.files.len(), 1, "{}", name);
+            assert_eq!(
+                root.files[0].path,
+                Path::new("archive").join(name).join("a.txt")
+            );
+            assert_eq!(root.folders.len(), 1, "{}", name);
+
+            let inner = get_archive_dir(Path::new(ROOT), &archive, Path::new("inner")).unwrap();
+            assert_eq!(inner.files.len(), 1, "{}", name);
+            assert_eq!(inner.folders.len(), 1, "{}", name);
+
+            assert!(get_archive_dir(Path::new(ROOT), &archive, Path::new("missing")).is_err());
+        }
+    }
+
+    #[test]
+    fn test_stat_member() {
+        let root = Path::new(ROOT);
+        let archive = root.join("archive/album.zip");
+        let file = stat_member(root, &archive, Path::new("inner/b.txt")).unwrap();
+        assert_eq!(
+            file.entry.path,
+            PathBuf::from("archive/album.zip/inner/b.txt")
+        );
+        assert!(file.entry.size > 0);
+        assert_eq!(file.kind, Kind::Text);
+        let folder = stat_member(root, &archive, Path::new("inner")).unwrap();
+        assert_eq!(folder.kind, Kind::Folder);
+        assert!(stat_member(root, &archive, Path::new("missing")).is_err());
+    }
+
+    #[test]
+    fn test_open_comic() {
+        let open = |p: &str| ArchiveKind::open(&Path::new(ROOT).join("archive").join(p));
+        assert_eq!(open("comic.cbz").unwrap(), ArchiveKind::Zip);
+        // Zip file with a .cbr extension
+        assert_eq!(open("comic.cbr").unwrap(), ArchiveKind::Zip);
+        assert!(open("unsupported.cbr").is_err());
+    }
+
+    #[test]
+    fn test_get_pages() {
+        let pages = get_pages(Path::new(ROOT), &Path::new(ROOT).join("archive/comic.cbz")).unwrap();
+        let names: Vec<&str> = pages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["page1.png", "page2.png", "page10.png"]);
+        assert_eq!(pages[0].path, PathBuf::from("archive/comic.cbz/page1.png"));
+    }
+
+    #[test]
+    fn test_read_member() {
+        for name in &["album.zip", "album.tar.gz"] {
+            let archive = Path::new(ROOT).join("archive").join(name);
+            let mut contents = String::new();
+            let found = read_member(&archive, Path::new("inner/deeper/c.txt"), |size, r| {
+                assert_eq!(size, 2);
+                r.read_to_string(&mut contents).map(|_| ())
+            });
+            assert!(found.unwrap());
+            assert_eq!(contents, "c\n");
+
+            let folder = read_member(&archive, Path::new("inner"), |_, _| unreachable!());
+            assert!(!folder.unwrap());
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::RwLock;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::{ErrorBadRequest, ErrorUnauthorized, InternalError},
+    get,
+    http::header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    post, web, Error, HttpMessage, HttpRequest, HttpResponse,
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::AuthMode;
+use crate::state::AppState;
+
+pub const SESSION_COOKIE: &str = "hview_session";
+const SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// Paths reachable without a session
+const PUBLIC_PATHS: [&str; 2] = ["/_api/login", "/_api/logout"];
+// Share links check their own token (see share.rs)
+const PUBLIC_PREFIXES: [&str; 5] = [
+    "/_s/",
+    "/_api/pages/_s/",
+    "/_api/list/_s/",
+    "/_api/stat/_s/",
+    "/_api/unlock/",
+];
+
+// Whether requests need an identity at all
+pub fn enabled(state: &AppState) -> bool {
+    state.users.is_some() || matches!(state.config.auth, AuthMode::Header(_))
+}
+
+// Authenticated user attached to each request
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug)]
+struct User {
+    hash: String,
+    groups: Vec<String>,
+}
+
+// Local user database
+// One user per line: "<name>:<argon2 PHC hash>[:<group>,<group>...]"
+// Lines starting with # are ignored
+#[derive(Debug, Default)]
+pub struct Users {
+    users: HashMap<String, User>,
+}
+
+impl Users {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let users = contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let mut parts = l.splitn(3, ':');
+                let name = parts.next()?.to_string();
+                let hash = parts.next()?.to_string();
+                let groups = parts
+                    .next()
+                    .map(|g| {
+                        g.split(',')
+                            .map(str::trim)
+                            .filter(|g| !g.is_empty())
+                            .map(String::from)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Some((name, User { hash, groups }))
+            })
+            .collect();
+
+        Self { users }
+    }
+
+    // Identity of a user vouched for by a proxy; unknown users have no groups
+    pub fn identity(&self, name: &str) -> Identity {
+        Identity {
+            name: name.to_string(),
+            groups: self
+                .users
+                .get(name)
+                .map(|u| u.groups.to_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn verify(&self, name: &str, password: &str) -> Option<Identity> {
+        let user = self.users.get(name)?;
+        if verify_password(password, &user.hash) {
+            Some(Identity {
+                name: name.to_string(),
+                groups: user.groups.to_owned(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    // bcrypt, as written by "htpasswd -B"
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+struct Session {
+    identity: Identity,
+    expires: Instant,
+}
+
+// In-memory sessions, keyed by the random token stored in SESSION_COOKIE
+#[derive(Default)]
+pub struct Sessions {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    pub fn create(&self, identity: Identity) -> String {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(48)
+            .map(char::from)
+            .collect();
+
+        let mut sessions = self.sessions.write().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(
+            token.to_owned(),
+            Session {
+                identity,
+                expires: now + SESSION_TTL,
+            },
+        );
+
+        token
+    }
+
+    pub fn get(&self, token: &str) -> Option<Identity> {
+        let sessions = self.sessions.read().unwrap();
+        let session = sessions.get(token)?;
+        if session.expires > Instant::now() {
+            Some(session.identity.to_owned())
+        } else {
+            None
+        }
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.sessions.write().unwrap().remove(token);
+    }
+}
+
+// Identity from an "Authorization: Basic ..." header value
+fn check_basic(state: &AppState, header: &str) -> Option<Identity> {
+    let credentials = base64::decode(header.strip_prefix("Basic ")?.trim()).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (name, password) = credentials.split_once(':')?;
+    state.users.as_ref()?.verify(name, password)
+}
+
+// Passwords are checked on the blocking pool, as hashing them takes a while,
+// and the outcome is kept for further requests with the same header
+async fn basic_identity(state: web::Data<AppState>, header: String) -> Option<Identity> {
+    let key = format!("{:x}", Sha256::digest(header.as_bytes()));
+    if let Some(known) = state.basic_cache.read().unwrap().get(&key) {
+        return known.to_owned();
+    }
+
+    let checked = state.clone();
+    let identity =
+        web::block(move || -> Result<Option<Identity>, ()> { Ok(check_basic(&checked, &header)) })
+            .await
+            .ok()?;
+
+    let mut cache = state.basic_cache.write().unwrap();
+    if cache.len() > 1000 {
+        cache.clear();
+    }
+    cache.insert(key, identity.to_owned());
+    identity
+}
+
+// Identity from the header set by a reverse proxy, only trusted from --trusted-proxies
+fn header_identity(state: &AppState, req: &ServiceRequest, header: &str) -> Option<Identity> {
+    let peer = req.peer_addr()?.ip();
+    if !state
+        .config
+        .trusted_proxies
+        .iter()
+        .any(|net| net.contains(&peer))
+    {
+        return None;
+    }
+
+    let name = req.headers().get(header)?.to_str().ok()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(match state.users.as_ref() {
+        Some(users) => users.identity(name),
+        None => Identity {
+            name: name.to_string(),
+            groups: Vec::new(),
+        },
+    })
+}
+
+async fn identify(state: &web::Data<AppState>, req: &ServiceRequest) -> Option<Identity> {
+    match &state.config.auth {
+        AuthMode::Session => req
+            .cookie(SESSION_COOKIE)
+            .and_then(|c| state.sessions.get(c.value())),
+        AuthMode::Basic => {
+            let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+            basic_identity(state.clone(), header.to_owned()).await
+        }
+        AuthMode::Header(header) => header_identity(state, req, header),
+    }
+}
+
+fn unauthorized(state: &AppState) -> Error {
+    match state.config.auth {
+        // Have the browser ask for credentials
+        AuthMode::Basic => InternalError::from_response(
+            "Login required",
+            HttpResponse::Unauthorized()
+                .header(WWW_AUTHENTICATE, "Basic realm=\"hview\", charset=\"UTF-8\"")
+                .finish(),
+        )
+        .into(),
+        _ => ErrorUnauthorized("Login required"),
+    }
+}
+
+// Middleware attaching the Identity of each request (see --auth),
+// and rejecting requests without one when authentication is enabled
+pub struct RequireAuth;
+
+impl<S, B> Transform<S> for RequireAuth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireAuthMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        }))
+    }
+}
+
+// The service is shared with the future checking the identity
+pub struct RequireAuthMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for RequireAuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let state = req
+                .app_data::<web::Data<AppState>>()
+                .expect("AppState is registered")
+                .clone();
+            let identity = identify(&state, &req).await;
+            // Relative to where the gallery is mounted
+            let path = req.match_info().unprocessed();
+            let public = !enabled(&state)
+                || PUBLIC_PATHS.contains(&path)
+                || PUBLIC_PREFIXES.iter().any(|p| path.starts_with(p));
+
+            match identity {
+                Some(identity) => {
+                    req.extensions_mut().insert(identity);
+                }
+                None if !public => return Err(unauthorized(&state)),
+                None => {}
+            }
+
+            let response = service.borrow_mut().call(req);
+            response.await
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[post("/_api/login")]
+pub async fn login(
+    state: web::Data<AppState>,
+    req: web::Json<LoginRequest>,
+) -> Result<HttpResponse, Error> {
+    if state.users.is_none() {
+        return Err(ErrorBadRequest("Authentication is not enabled"));
+    } else if state.config.auth != AuthMode::Session {
+        return Err(ErrorBadRequest("Login is handled by the --auth mode"));
+    }
+
+    let LoginRequest { username, password } = req.into_inner();
+    let users = state.clone();
+    let identity = web::block(move || -> Result<Option<Identity>, ()> {
+        Ok(users.users.as_ref().unwrap().verify(&username, &password))
+    })
+    .await?
+    .ok_or_else(|| ErrorUnauthorized("Invalid username or password"))?;
+
+    let token = state.sessions.create(identity.to_owned());
+    let cookie = Cookie::build(SESSION_COOKIE, token)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+
+    Ok(HttpResponse::Ok().cookie(cookie).json(identity))
+}
+
+#[post("/_api/logout")]
+pub async fn logout(state: web::Data<AppState>, req: HttpRequest) -> HttpResponse {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        state.sessions.remove(cookie.value());
+    }
+
+    let cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    HttpResponse::Ok().del_cookie(&cookie).finish()
+}
+
+#[get("/_api/whoami")]
+pub async fn whoami(identity: Option<web::ReqData<Identity>>) -> HttpResponse {
+    match identity {
+        Some(identity) => HttpResponse::Ok().json(identity.into_inner()),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_users() {
+        let hash = hash_password("secret").unwrap();
+        let users = Users::parse(&format!(
+            "# comment\n\nalice:{}:team, admins\nbob:invalid\n",
+            hash
+        ));
+
+        let alice = users.verify("alice", "secret").unwrap();
+        assert_eq!(alice.groups, vec!["team", "admins"]);
+        assert!(users.verify("alice", "wrong").is_none());
+        assert!(users.verify("bob", "invalid").is_none());
+        assert!(users.verify("carol", "secret").is_none());
+    }
+
+    #[test]
+    fn test_bcrypt() {
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    fn test_auth_mode() {
+        assert_eq!("session".parse(), Ok(AuthMode::Session));
+        assert_eq!("basic".parse(), Ok(AuthMode::Basic));
+        assert_eq!(
+            "header=X-Remote-User".parse(),
+            Ok(AuthMode::Header(String::from("X-Remote-User")))
+        );
+        assert!("header=".parse::<AuthMode>().is_err());
+        assert!("ldap".parse::<AuthMode>().is_err());
+    }
+
+    #[test]
+    fn test_sessions() {
+        let sessions = Sessions::default();
+        let token = sessions.create(Identity {
+            name: String::from("alice"),
+            groups: Vec::new(),
+        });
+        assert_eq!(sessions.get(&token).unwrap().name, "alice");
+        assert!(sessions.get("unknown").is_none());
+
+        sessions.remove(&token);
+        assert!(sessions.get(&token).is_none());
+    }
+}
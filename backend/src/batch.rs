@@ -0,0 +1,312 @@
+use std::fs::{self, File};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+
+use actix_files::NamedFile;
+use actix_web::{
+    error::{ErrorBadRequest, ErrorForbidden},
+    post, web, Error, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::acl::{self, Access};
+use crate::auth::Identity;
+use crate::dir::FileEntry;
+use crate::state::{AppState, Library};
+
+#[derive(Deserialize, Debug)]
+pub struct BatchRequest {
+    paths: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MoveRequest {
+    paths: Vec<String>,
+    dest: String,
+}
+
+// Downloads are submitted from a plain <form> so the browser
+// handles the response as a file; `paths` is a JSON encoded list
+#[derive(Deserialize, Debug)]
+pub struct DownloadForm {
+    paths: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct BatchResult {
+    done: Vec<String>,
+    failed: Vec<BatchFailure>,
+}
+
+// A requested path, resolved in the library it was requested through
+struct Selected {
+    name: String,
+    library: String,
+    path: PathBuf,
+}
+
+impl Selected {
+    fn library<'a>(&self, state: &'a AppState) -> &'a Library {
+        state.library(&self.library).expect("Resolved library")
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchFailure {
+    path: String,
+    error: String,
+}
+
+impl BatchResult {
+    fn record(&mut self, path: String, result: std::io::Result<()>) {
+        match result {
+            Ok(_) => self.done.push(path),
+            Err(err) => self.failed.push(BatchFailure {
+                path,
+                error: err.to_string(),
+            }),
+        }
+    }
+}
+
+// Resolve every requested path, refusing library roots, anything outside of them
+// and anything the user lacks `needed` access to
+// Changes are refused in read-only libraries, and in folders holding anything the
+// user can't change
+fn resolve_all(
+    state: &AppState,
+    paths: &[String],
+    identity: Option<&Identity>,
+    needed: Access,
+) -> Result<Vec<Selected>, Error> {
+    if paths.is_empty() {
+        return Err(ErrorBadRequest("No paths selected"));
+    }
+
+    paths
+        .iter()
+        .map(|p| {
+            let (library, path) = state
+                .resolve(p)
+                .map_err(|_| ErrorBadRequest("Invalid path"))?;
+            if path == library.root {
+                return Err(ErrorBadRequest("Invalid path"));
+            }
+            if needed >= Access::Write {
+                deny_read_only(library)?;
+                if acl::access_within(state, identity, library, &path) < needed {
+                    return Err(ErrorForbidden("Access denied"));
+                }
+            }
+            acl::require(state, identity, library, &path, needed)?;
+            Ok(Selected {
+                name: p.to_owned(),
+                library: library.name.to_owned(),
+                path,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn deny_read_only(library: &Library) -> Result<(), Error> {
+    if library.read_only {
+        Err(ErrorForbidden("Read-only"))
+    } else {
+        Ok(())
+    }
+}
+
+// Add a file or (recursively) a folder to the archive under `name`
+// Nested entries the user can not read are left out
+fn zip_path<W: Write + Seek>(
+    state: &AppState,
+    zip: &mut ZipWriter<W>,
+    library: &Library,
+    path: &Path,
+    name: &str,
+    identity: Option<&Identity>,
+) -> std::io::Result<()> {
+    let options = FileOptions::default();
+    if path.is_dir() {
+        zip.add_directory(format!("{}/", name), options)?;
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+            // Thumbnails are not part of the selection
+            if entry_name == ".th"
+                || library.hides(&entry_name)
+                || acl::access(state, identity, library, &entry.path()) < Access::Read
+            {
+                continue;
+            }
+            zip_path(
+                state,
+                zip,
+                library,
+                &entry.path(),
+                &format!("{}/{}", name, entry_name),
+                identity,
+            )?;
+        }
+    } else {
+        zip.start_file(name, options)?;
+        std::io::copy(&mut File::open(path)?, zip)?;
+    }
+
+    Ok(())
+}
+
+fn build_archive(
+    state: &AppState,
+    paths: Vec<Selected>,
+    identity: Option<&Identity>,
+) -> std::io::Result<File> {
+    let mut zip = ZipWriter::new(tempfile::tempfile()?);
+    for selected in paths {
+        let path = &selected.path;
+        let name = path
+            .file_name()
+            .expect("Valid file name")
+            .to_string_lossy()
+            .to_string();
+        let library = selected.library(state);
+        zip_path(state, &mut zip, library, path, &name, identity)?;
+    }
+
+    let mut file = zip.finish()?;
+    file.seek(std::io::SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+// Remove a file or folder, along with the file's thumbnail
+fn delete_path(path: &Path, thumb_format: &str) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)?;
+        if let Ok(thumb) = FileEntry::file_path_to_thumb(path, thumb_format) {
+            if thumb.exists() {
+                fs::remove_file(thumb)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Move a file or folder into `dest`, carrying the file's thumbnail along
+// `dest` must be in the same library as `path`
+fn move_path(path: &Path, dest: &Path, thumb_format: &str) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+
+    if dest.starts_with(path) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Cannot move a folder into itself",
+        ));
+    }
+
+    let target = dest.join(path.file_name().expect("Valid file name"));
+    if target.exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, "Destination exists"));
+    }
+
+    let thumb = FileEntry::file_path_to_thumb(path, thumb_format)
+        .ok()
+        .filter(|t| path.is_file() && t.exists());
+    fs::rename(path, &target)?;
+
+    if let (Some(thumb), Ok(target_thumb)) =
+        (thumb, FileEntry::file_path_to_thumb(&target, thumb_format))
+    {
+        fs::create_dir_all(dest.join(".th"))?;
+        fs::rename(thumb, target_thumb)?;
+    }
+
+    Ok(())
+}
+
+#[post("/_api/batch/download")]
+pub async fn download(
+    state: web::Data<AppState>,
+    form: web::Form<DownloadForm>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<NamedFile, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let paths: Vec<String> =
+        serde_json::from_str(&form.paths).map_err(|_| ErrorBadRequest("Invalid path list"))?;
+    let paths = resolve_all(&state, &paths, identity.as_ref(), Access::Read)?;
+
+    let file = web::block(move || build_archive(&state, paths, identity.as_ref())).await?;
+    Ok(NamedFile::from_file(file, "hview.zip")?)
+}
+
+#[post("/_api/batch/delete")]
+pub async fn delete(
+    state: web::Data<AppState>,
+    req: web::Json<BatchRequest>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let paths = resolve_all(&state, &req.paths, identity.as_ref(), Access::Write)?;
+
+    let result = web::block(move || -> Result<BatchResult, ()> {
+        let mut result = BatchResult::default();
+        for selected in paths {
+            let library = selected.library(&state);
+            let path = &selected.path;
+            result.record(selected.name.to_owned(), delete_path(path, &library.format));
+            library.listings.invalidate(path);
+            library.folder_stats.invalidate(path);
+        }
+        Ok(result)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[post("/_api/batch/move")]
+pub async fn move_paths(
+    state: web::Data<AppState>,
+    req: web::Json<MoveRequest>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let paths = resolve_all(&state, &req.paths, identity.as_ref(), Access::Write)?;
+    let (library, dest) = state
+        .resolve(&req.dest)
+        .map_err(|_| ErrorBadRequest("Invalid destination"))?;
+    deny_read_only(library)?;
+    acl::require(&state, identity.as_ref(), library, &dest, Access::Write)?;
+    if !dest.is_dir() {
+        return Err(ErrorBadRequest("Destination is not a folder"));
+    }
+    let dest_library = library.name.to_owned();
+
+    let result = web::block(move || -> Result<BatchResult, ()> {
+        let mut result = BatchResult::default();
+        let library = state.library(&dest_library).expect("Resolved library");
+        for selected in paths {
+            let path = &selected.path;
+            let moved = if selected.library == library.name {
+                let moved = move_path(path, &dest, &library.format);
+                library.listings.invalidate(path);
+                library.listings.invalidate(&dest);
+                library.folder_stats.invalidate(path);
+                library.folder_stats.invalidate(&dest);
+                moved
+            } else {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Cannot move between libraries",
+                ))
+            };
+            result.record(selected.name, moved);
+        }
+        Ok(result)
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
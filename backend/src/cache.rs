@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::dir::Dir;
+
+// Recently served folder listings of a library, keyed by folder and its modification time
+// so changes made outside of hview are picked up too
+// Once `capacity` listings are kept, the least recently used one is dropped
+// Modification times of a folder and of its thumbnails folder, if any: new thumbnails
+// change the listing without changing the folder
+pub type Modified = (SystemTime, Option<SystemTime>);
+
+#[derive(Debug)]
+pub struct ListingCache {
+    capacity: usize,
+    inner: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    listings: HashMap<PathBuf, Cached>,
+    // Incremented on every use, to find the least recently used listing
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Cached {
+    modified: Modified,
+    dir: Dir,
+    used: u64,
+}
+
+impl ListingCache {
+    // A `capacity` of 0 disables the cache
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Entries::default()),
+        }
+    }
+
+    // Listing of `path`, unless the folder changed since it was cached
+    pub fn get(&self, path: &Path, modified: Modified) -> Option<Dir> {
+        let mut entries = self.inner.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        match entries.listings.get_mut(path) {
+            Some(cached) if cached.modified == modified => {
+                cached.used = clock;
+                Some(cached.dir.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, path: PathBuf, modified: Modified, dir: Dir) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.inner.lock().unwrap();
+        if !entries.listings.contains_key(&path) && entries.listings.len() >= self.capacity {
+            let oldest = entries
+                .listings
+                .iter()
+                .min_by_key(|(_, cached)| cached.used)
+                .map(|(path, _)| path.to_owned());
+            if let Some(oldest) = oldest {
+                entries.listings.remove(&oldest);
+            }
+        }
+
+        entries.clock += 1;
+        let used = entries.clock;
+        entries.listings.insert(
+            path,
+            Cached {
+                modified,
+                dir,
+                used,
+            },
+        );
+    }
+
+    // Drop listings a change to `path` shows up in: its own, those of the folders inside it,
+    // and those of the folders containing it (their thumbnails may come from it)
+    pub fn invalidate(&self, path: &Path) {
+        let mut entries = self.inner.lock().unwrap();
+        entries
+            .listings
+            .retain(|cached, _| !cached.starts_with(path) && !path.starts_with(cached));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lru() {
+        let cache = ListingCache::new(2);
+        let time = (SystemTime::UNIX_EPOCH, None);
+        cache.insert(PathBuf::from("/a"), time, Dir::new(String::from("a")));
+        cache.insert(PathBuf::from("/b"), time, Dir::new(String::from("b")));
+        assert!(cache.get(Path::new("/a"), time).is_some());
+
+        // "/b" is the least recently used
+        cache.insert(PathBuf::from("/c"), time, Dir::new(String::from("c")));
+        assert!(cache.get(Path::new("/b"), time).is_none());
+        assert!(cache.get(Path::new("/a"), time).is_some());
+
+        // Changed since, or got new thumbnails
+        let later = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        assert!(cache.get(Path::new("/a"), (later, None)).is_none());
+        assert!(cache
+            .get(Path::new("/a"), (SystemTime::UNIX_EPOCH, Some(later)))
+            .is_none());
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = ListingCache::new(8);
+        let time = (SystemTime::UNIX_EPOCH, None);
+        for path in &["/r", "/r/a", "/r/a/b", "/r/c"] {
+            cache.insert(PathBuf::from(path), time, Dir::new(path.to_string()));
+        }
+
+        cache.invalidate(Path::new("/r/a"));
+        assert!(cache.get(Path::new("/r"), time).is_none());
+        assert!(cache.get(Path::new("/r/a"), time).is_none());
+        assert!(cache.get(Path::new("/r/a/b"), time).is_none());
+        assert!(cache.get(Path::new("/r/c"), time).is_some());
+    }
+}
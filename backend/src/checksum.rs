@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use actix_web::{
+    error::ErrorBadRequest,
+    get,
+    http::header::{HeaderName, HeaderValue},
+    web, Either, Error, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::Identity;
+use crate::check_read;
+use crate::dir::DirError;
+use crate::state::AppState;
+use crate::FileResponse;
+
+// Digests kept in memory, the least recently used one is dropped past this
+pub(crate) const CHECKSUMS: usize = 4096;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Algo {
+    #[default]
+    Sha256,
+    Blake3,
+    Md5,
+}
+
+impl Algo {
+    // Name in `Digest` and `Repr-Digest` headers
+    fn http_name(self) -> &'static str {
+        match self {
+            Algo::Sha256 => "sha-256",
+            Algo::Blake3 => "blake3",
+            Algo::Md5 => "md5",
+        }
+    }
+
+    fn from_http_name(name: &str) -> Option<Self> {
+        [Algo::Sha256, Algo::Blake3, Algo::Md5]
+            .iter()
+            .copied()
+            .find(|algo| algo.http_name().eq_ignore_ascii_case(name))
+    }
+}
+
+// Digest of the content of a file, read in chunks
+pub fn digest(path: &Path, algo: Algo) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    Ok(match algo {
+        Algo::Sha256 => hash_with(&mut file, Sha256::new())?.finalize().to_vec(),
+        Algo::Md5 => hash_with(&mut file, md5::Md5::new())?.finalize().to_vec(),
+        Algo::Blake3 => hash_with(&mut file, blake3::Hasher::new())?
+            .finalize()
+            .as_bytes()
+            .to_vec(),
+    })
+}
+
+fn hash_with<W: Write>(file: &mut File, mut hasher: W) -> io::Result<W> {
+    io::copy(file, &mut hasher)?;
+    Ok(hasher)
+}
+
+// Digests of recently hashed files, keyed by path and algorithm, valid as long as
+// the size and modification time of the file are the same
+#[derive(Debug)]
+pub struct ChecksumCache {
+    capacity: usize,
+    inner: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    digests: HashMap<(PathBuf, Algo), Cached>,
+    // Incremented on every use, to find the least recently used digest
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Cached {
+    size: u64,
+    modified: SystemTime,
+    digest: Vec<u8>,
+    used: u64,
+}
+
+impl ChecksumCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Entries::default()),
+        }
+    }
+
+    // Digest of `path`, hashed again when the file changed since
+    pub fn get(&self, path: &Path, algo: Algo) -> io::Result<Vec<u8>> {
+        let meta = fs::metadata(path)?;
+        let (size, modified) = (meta.len(), meta.modified()?);
+        let key = (path.to_owned(), algo);
+        {
+            let mut entries = self.inner.lock().unwrap();
+            entries.clock += 1;
+            let clock = entries.clock;
+            match entries.digests.get_mut(&key) {
+                Some(cached) if cached.size == size && cached.modified == modified => {
+                    cached.used = clock;
+                    return Ok(cached.digest.clone());
+                }
+                _ => (),
+            }
+        }
+
+        // Not locked while hashing, a file changed meanwhile is hashed again next time
+        let digest = digest(path, algo)?;
+        let mut entries = self.inner.lock().unwrap();
+        if !entries.digests.contains_key(&key) && entries.digests.len() >= self.capacity {
+            let oldest = entries
+                .digests
+                .iter()
+                .min_by_key(|(_, cached)| cached.used)
+                .map(|(key, _)| key.to_owned());
+            if let Some(oldest) = oldest {
+                entries.digests.remove(&oldest);
+            }
+        }
+        entries.clock += 1;
+        let used = entries.clock;
+        entries.digests.insert(
+            key,
+            Cached {
+                size,
+                modified,
+                digest: digest.clone(),
+                used,
+            },
+        );
+        Ok(digest)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HashQuery {
+    #[serde(default)]
+    algo: Algo,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Checksum {
+    algo: Algo,
+    size: u64,
+    // Hex encoded, as printed by sha256sum, b3sum and md5sum
+    digest: String,
+}
+
+// Digest of a file, eg to check a copy of it
+#[get("/_api/hash/{file:.*}")]
+pub async fn hash(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<HashQuery>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let (library, path) = state.resolve(req.match_info().query("file"))?;
+    check_read(&state, identity.as_deref(), library, &path)?;
+    if !path.is_file() {
+        return Err(ErrorBadRequest("Not a file"));
+    }
+
+    let algo = query.algo;
+    let size = fs::metadata(&path).map_err(DirError::from)?.len();
+    let digest = file_digest(&state, path, algo).await?;
+    let digest = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(HttpResponse::Ok().json(Checksum { algo, size, digest }))
+}
+
+async fn file_digest(
+    state: &web::Data<AppState>,
+    path: PathBuf,
+    algo: Algo,
+) -> Result<Vec<u8>, Error> {
+    let state = state.clone();
+    Ok(
+        web::block(move || state.checksums.get(&path, algo).map_err(DirError::from))
+            .await
+            .map_err(DirError::from)?,
+    )
+}
+
+// Algorithm preferred in a `Want-Repr-Digest` (eg "sha-256=10, md5=1")
+// or `Want-Digest` (eg "SHA-256;q=0.5, MD5") header, if any is supported
+fn wanted(header: &str) -> Option<Algo> {
+    let mut best = None;
+    let mut best_weight = 0.0;
+    for item in header.split(',') {
+        let item = item.trim();
+        let (name, weight) = if let Some((name, q)) = item.split_once(";q=") {
+            (name, q.trim().parse().unwrap_or(0.0))
+        } else if let Some((name, preference)) = item.split_once('=') {
+            // From 0 to 10 in Want-Repr-Digest
+            (name, preference.trim().parse::<f32>().unwrap_or(0.0) / 10.0)
+        } else {
+            (item, 1.0)
+        };
+        if let Some(algo) = Algo::from_http_name(name.trim()) {
+            if weight > best_weight {
+                best = Some(algo);
+                best_weight = weight;
+            }
+        }
+    }
+    best
+}
+
+// `file` as is, or with the digest of its content when the request asks for it with
+// `Want-Repr-Digest` (RFC 9530) or `Want-Digest` (RFC 3230)
+pub(crate) async fn with_digest(
+    state: &web::Data<AppState>,
+    req: &HttpRequest,
+    path: PathBuf,
+    file: FileResponse,
+) -> Result<FileResponse, Error> {
+    let file = match file {
+        Either::A(file) => file,
+        member => return Ok(member),
+    };
+    let header = |name| req.headers().get(name).and_then(|h| h.to_str().ok());
+    let repr = header("Want-Repr-Digest").and_then(wanted);
+    let instance = header("Want-Digest").and_then(wanted);
+    // Archive members don't have a path of their own
+    if (repr.is_none() && instance.is_none()) || !path.is_file() {
+        return Ok(Either::A(file));
+    }
+
+    let mut headers = vec![];
+    if let Some(algo) = repr {
+        let digest = base64::encode(file_digest(state, path.clone(), algo).await?);
+        headers.push(("repr-digest", format!("{}=:{}:", algo.http_name(), digest)));
+    }
+    if let Some(algo) = instance {
+        let digest = base64::encode(file_digest(state, path, algo).await?);
+        headers.push(("digest", format!("{}={}", algo.http_name(), digest)));
+    }
+    let mut response = file.into_response(req)?;
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(name), value);
+        }
+    }
+    Ok(Either::B(response))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc");
+        fs::write(&path, "abc").unwrap();
+        let hex = |algo| {
+            digest(&path, algo)
+                .unwrap()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        };
+        assert_eq!(
+            hex(Algo::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hex(Algo::Md5), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(Algo::Blake3),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "one").unwrap();
+        let cache = ChecksumCache::new(1);
+        let first = cache.get(&path, Algo::Md5).unwrap();
+        assert_eq!(first, digest(&path, Algo::Md5).unwrap());
+
+        // Same size and time: answered from the cache
+        let file = File::options().write(true).open(&path).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "two").unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(cache.get(&path, Algo::Md5).unwrap(), first);
+
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(
+            cache.get(&path, Algo::Md5).unwrap(),
+            digest(&path, Algo::Md5).unwrap()
+        );
+
+        // Only one digest is kept
+        cache.get(&path, Algo::Sha256).unwrap();
+        assert_eq!(cache.inner.lock().unwrap().digests.len(), 1);
+    }
+
+    #[test]
+    fn test_wanted() {
+        assert_eq!(wanted("sha-256=1"), Some(Algo::Sha256));
+        assert_eq!(wanted("md5=3, sha-256=10"), Some(Algo::Sha256));
+        assert_eq!(wanted("sha-512=10, blake3=2"), Some(Algo::Blake3));
+        assert_eq!(wanted("SHA-256;q=0.3, MD5"), Some(Algo::Md5));
+        assert_eq!(wanted("MD5"), Some(Algo::Md5));
+        assert_eq!(wanted("sha-256=0"), None);
+        assert_eq!(wanted("crc32c"), None);
+    }
+}
@@ -1,15 +1,156 @@
-use lazy_static::lazy_static;
-use std::path::Path;
-use structopt::StructOpt;
+use ipnet::IpNet;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::{clap::ArgMatches, StructOpt};
+
+// How requests are authenticated (see auth.rs)
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthMode {
+    // Login page and session cookie
+    Session,
+    // HTTP Basic authentication
+    Basic,
+    // User name set in a header by a trusted reverse proxy
+    Header(String),
+}
+
+impl FromStr for AuthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session" => Ok(Self::Session),
+            "basic" => Ok(Self::Basic),
+            _ => match s.strip_prefix("header=") {
+                Some(name) if !name.is_empty() => Ok(Self::Header(name.to_string())),
+                _ => Err(format!(
+                    "Unknown auth mode \"{}\", expected session, basic or header=<name>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+// Whether dot files are listed and served
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hidden {
+    Show,
+    Hide,
+}
+
+impl FromStr for Hidden {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "show" => Ok(Self::Show),
+            "hide" => Ok(Self::Hide),
+            _ => Err(format!("expected show or hide, found \"{}\"", s)),
+        }
+    }
+}
+
+// How folder covers are picked from the thumbnails of a folder,
+// unless it has an explicit cover (see cover.rs)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cover {
+    // First in sort order
+    First,
+    // Most recently made thumbnail
+    Newest,
+    // Random, but the same for a whole day
+    Daily,
+    // Random on every listing
+    Random,
+    // 2x2 mosaic of thumbnails from the whole subtree, made with imagemagick
+    Mosaic,
+}
+
+impl FromStr for Cover {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "newest" => Ok(Self::Newest),
+            "daily" => Ok(Self::Daily),
+            "random" => Ok(Self::Random),
+            "mosaic" => Ok(Self::Mosaic),
+            _ => Err(format!(
+                "expected first, newest, daily, random or mosaic, found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+// A named root dir, as "<name>=<dir>[;<option>=<value>...]"
+// Options (read_only, format, hidden) default to the global ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct LibrarySpec {
+    pub name: String,
+    pub dir: PathBuf,
+    pub read_only: Option<bool>,
+    pub format: Option<String>,
+    pub hidden: Option<Hidden>,
+}
+
+impl FromStr for LibrarySpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(';');
+        let (name, dir) = parts
+            .next()
+            .and_then(|p| p.split_once('='))
+            .ok_or_else(|| format!("expected <name>=<dir>, found \"{}\"", s))?;
+        let mut spec = Self {
+            name: name.trim().to_string(),
+            dir: PathBuf::from(dir.trim()),
+            read_only: None,
+            format: None,
+            hidden: None,
+        };
+
+        for option in parts {
+            let (key, value) = option.split_once('=').unwrap_or((option, "true"));
+            let value = value.trim();
+            match key.trim() {
+                "read_only" => spec.read_only = Some(bool::parse_value(value)?),
+                "format" => spec.format = Some(value.to_string()),
+                "hidden" => spec.hidden = Some(value.parse()?),
+                key => return Err(format!("unknown library option \"{}\"", key)),
+            }
+        }
+
+        Ok(spec)
+    }
+}
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "hview")]
 pub struct Config {
+    //// TOML config file; any option below can be set in it (eg, `read_only = true`)
+    //// Options can also be set with HVIEW_<OPTION> env vars (eg, HVIEW_READ_ONLY=true)
+    //// Flags override env vars, which override the file; switches take an optional value
+    //// to turn off what the file or env vars turned on (eg, `--read-only=false`)
+    #[structopt(long, env = "HVIEW_CONFIG")]
+    pub config: Option<String>,
+
     //// Root dir containing files to serve
     #[structopt(short, long, default_value = "./test-fixtures/")]
     pub dir: String,
 
-    //// Basepath: Additional path for URI's (eg, "subdir" adds "/subdir/" to add URIs)
+    //// Named root dirs served instead of --dir, as "<name>=<dir>", eg "photos=/mnt/photos"
+    //// Each can override read_only, format and hidden, eg "docs=/srv/docs;read_only=true"
+    #[structopt(long = "library", number_of_values = 1)]
+    pub libraries: Vec<LibrarySpec>,
+
+    //// Basepath: prefix all routes are served under (eg, "/sub/" serves "/sub/photos/")
+    //// Slashes are added where missing, so "sub" is the same as "/sub/"
     #[structopt(long, default_value = "/")]
     pub basepath: String,
 
@@ -22,7 +163,15 @@ pub struct Config {
     pub port: u16,
 
     //// Verbose log output
-    #[structopt(long, short)]
+    #[structopt(
+        long,
+        short,
+        takes_value = true,
+        min_values = 0,
+        max_values = 1,
+        require_equals = true,
+        value_name = "bool"
+    )]
     pub verbose: bool,
 
     //// Thumbnail format; becomes thumbnail extension as well
@@ -31,17 +180,478 @@ pub struct Config {
     pub format: String,
 
     //// Read-only; disables modification/deletion of files
-    #[structopt(long)]
+    #[structopt(
+        long,
+        takes_value = true,
+        min_values = 0,
+        max_values = 1,
+        require_equals = true,
+        value_name = "bool"
+    )]
     pub read_only: bool,
 
     //// Disable thumbnails
-    #[structopt(long, short)]
+    #[structopt(
+        long,
+        short,
+        takes_value = true,
+        min_values = 0,
+        max_values = 1,
+        require_equals = true,
+        value_name = "bool"
+    )]
     pub no_thumbs: bool,
+
+    //// Folder covers without an explicit cover (.th/cover.<format> or cover.jpg):
+    //// "first", "newest", "daily" (random, changes once a day), "random"
+    //// or "mosaic" (2x2 grid of thumbnails from the folder and its sub folders, needs imagemagick)
+    #[structopt(long, default_value = "daily")]
+    pub cover: Cover,
+
+    //// Dot files: "show" or "hide" (neither listed nor served)
+    #[structopt(long, default_value = "show")]
+    pub hidden: Hidden,
+
+    //// Number of folder listings kept in memory (per library), 0 to disable
+    //// Listings are read again when their folder changes
+    #[structopt(long, default_value = "256")]
+    pub listing_cache: usize,
+
+    //// Users file; enables authentication when set
+    //// One user per line as "<name>:<hash>[:<groups>]" (see --hash-password)
+    //// Hashes are argon2 or bcrypt, so htpasswd files made with "htpasswd -B" work too
+    #[structopt(long)]
+    pub users: Option<String>,
+
+    //// Authentication mode: "session" (login page), "basic" (HTTP Basic auth)
+    //// or "header=<name>" (user name set by a reverse proxy, eg "header=X-Remote-User")
+    //// session and basic check passwords against --users
+    #[structopt(long, default_value = "session")]
+    pub auth: AuthMode,
+
+    //// Proxies allowed to set the --auth header, as comma separated CIDRs
+    #[structopt(long, default_value = "127.0.0.1/32,::1/128", use_delimiter = true)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    //// Access control list file; restricts users and groups to path prefixes
+    //// One rule per line as "<user|@group|*> <path prefix> <none|read|write|admin>"
+    #[structopt(long)]
+    pub acl: Option<String>,
+
+    //// Share links file; keeps links (and their signing secret) across restarts
+    #[structopt(long)]
+    pub shares: Option<String>,
+
+    //// Read a password from stdin, print its hash for the users file and exit
+    #[structopt(long)]
+    pub hash_password: bool,
+}
+
+// Option values from the config file or env vars, before parsing
+// Each value remembers where it came from for error messages
+type Values = BTreeMap<String, (String, String)>;
+
+// Parsing of values from the config file and env vars
+trait ConfigValue: Sized {
+    fn parse_value(value: &str) -> Result<Self, String>;
+}
+
+macro_rules! from_str_value {
+    ($($t:ty),*) => {
+        $(impl ConfigValue for $t {
+            fn parse_value(value: &str) -> Result<Self, String> {
+                value.parse().map_err(|e| format!("{}", e))
+            }
+        })*
+    };
+}
+from_str_value!(String, u16, usize, AuthMode, Hidden, Cover);
+
+impl ConfigValue for bool {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        match value {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Err(format!("expected true or false, found \"{}\"", value)),
+        }
+    }
+}
+
+impl ConfigValue for Option<String> {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        Ok(Some(value.to_string()).filter(|v| !v.is_empty()))
+    }
+}
+
+impl ConfigValue for Vec<IpNet> {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        value
+            .split(',')
+            .map(|v| v.trim().parse().map_err(|e| format!("\"{}\": {}", v, e)))
+            .collect()
+    }
+}
+
+impl ConfigValue for Vec<LibrarySpec> {
+    fn parse_value(value: &str) -> Result<Self, String> {
+        value.split(',').map(str::parse).collect()
+    }
+}
+
+// Options that can be set from the config file and env vars
+// Flags given on the command line are left as they are
+macro_rules! layered_options {
+    ($($field:ident),*) => {
+        const OPTIONS: &[&str] = &[$(stringify!($field)),*];
+
+        impl Config {
+            fn apply(&mut self, matches: &ArgMatches, values: &Values) -> Result<(), String> {
+                $(
+                    // Args are named like their flag, eg "read-only"
+                    if matches.occurrences_of(stringify!($field).replace('_', "-")) == 0 {
+                        if let Some((source, value)) = values.get(stringify!($field)) {
+                            self.$field = ConfigValue::parse_value(value).map_err(|e| {
+                                format!("Invalid `{}` ({}): {}", stringify!($field), source, e)
+                            })?;
+                        }
+                    }
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+layered_options!(
+    dir,
+    libraries,
+    basepath,
+    host,
+    port,
+    verbose,
+    format,
+    read_only,
+    no_thumbs,
+    cover,
+    hidden,
+    listing_cache,
+    users,
+    auth,
+    trusted_proxies,
+    acl,
+    shares
+);
+
+// Values from a TOML config file; arrays become comma separated lists
+// and `[libraries.<name>]` tables become library specs
+fn file_values(contents: &str, source: &str) -> Result<Values, String> {
+    let table: toml::value::Table =
+        toml::from_str(contents).map_err(|e| format!("{}: {}", source, e))?;
+
+    let mut values = Values::new();
+    for (key, value) in table {
+        if !OPTIONS.contains(&key.as_str()) {
+            return Err(format!("Unknown option `{}` ({})", key, source));
+        }
+
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|i| {
+                    i.as_str()
+                        .map(String::from)
+                        .unwrap_or_else(|| i.to_string())
+                })
+                .collect::<Vec<String>>()
+                .join(","),
+            toml::Value::Table(libraries) if key == "libraries" => library_values(libraries)
+                .map_err(|e| format!("Invalid `{}` ({}): {}", key, source, e))?,
+            _ => return Err(format!("Invalid `{}` ({}): unsupported value", key, source)),
+        };
+        values.insert(key, (source.to_string(), value));
+    }
+
+    Ok(values)
+}
+
+// `[libraries.<name>]` tables as "<name>=<dir>;<option>=<value>..." specs
+fn library_values(libraries: toml::value::Table) -> Result<String, String> {
+    let mut specs = Vec::new();
+    for (name, library) in libraries {
+        let table = library
+            .as_table()
+            .ok_or_else(|| format!("library \"{}\" is not a table", name))?;
+        let mut spec = match table.get("dir").and_then(|d| d.as_str()) {
+            Some(dir) => format!("{}={}", name, dir),
+            None => return Err(format!("library \"{}\" has no dir", name)),
+        };
+        for (key, value) in table.iter().filter(|(k, _)| *k != "dir") {
+            let value = value
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| value.to_string());
+            spec.push_str(&format!(";{}={}", key, value));
+        }
+        specs.push(spec);
+    }
+
+    Ok(specs.join(","))
+}
+
+// Values from HVIEW_<OPTION> env vars
+fn env_values<I: Iterator<Item = (String, String)>>(vars: I) -> Values {
+    vars.filter_map(|(key, value)| {
+        let option = key.strip_prefix("HVIEW_")?.to_lowercase();
+        if OPTIONS.contains(&option.as_str()) {
+            Some((option, (key, value)))
+        } else {
+            None
+        }
+    })
+    .collect()
+}
+
+impl Config {
+    // Config from the command line, the config file (--config) and env vars
+    pub fn load() -> Result<Self, String> {
+        Self::load_from(Self::clap().get_matches(), std::env::vars())
+    }
+
+    fn load_from<I: Iterator<Item = (String, String)>>(
+        matches: ArgMatches,
+        vars: I,
+    ) -> Result<Self, String> {
+        let mut config = Self::from_clap(&matches);
+
+        let mut values = match &config.config {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read config file {}: {}", path, e))?;
+                file_values(&contents, path)?
+            }
+            None => Values::new(),
+        };
+        values.extend(env_values(vars));
+
+        config.apply(&matches, &values)?;
+        config.apply_switches(&matches)?;
+        config.basepath = normalize_basepath(&config.basepath);
+        // Only hashes a password, nothing is served
+        if !config.hash_password {
+            config.validate()?;
+        }
+        Ok(config)
+    }
+
+    // Switches given a value on the command line, eg `--read-only=false`
+    // (given without one, structopt already set them)
+    fn apply_switches(&mut self, matches: &ArgMatches) -> Result<(), String> {
+        let switches = [
+            ("verbose", &mut self.verbose),
+            ("read-only", &mut self.read_only),
+            ("no-thumbs", &mut self.no_thumbs),
+        ];
+        for (name, switch) in switches {
+            if let Some(value) = matches.value_of(name) {
+                *switch =
+                    bool::parse_value(value).map_err(|e| format!("Invalid `--{}`: {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.libraries.is_empty() && !Path::new(&self.dir).is_dir() {
+            return Err(format!("Invalid `dir`: {:?} is not a directory", self.dir));
+        }
+        for (i, library) in self.libraries.iter().enumerate() {
+            let name = &library.name;
+            // Names become the first URL segment, next to "/_s/" and "/_api/"
+            if name.is_empty()
+                || name.starts_with('_')
+                || !name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("Invalid `libraries`: invalid name {:?}", name));
+            }
+            if self.libraries[..i].iter().any(|l| &l.name == name) {
+                return Err(format!("Invalid `libraries`: duplicate name {:?}", name));
+            }
+            if !library.dir.is_dir() {
+                return Err(format!(
+                    "Invalid `libraries`: {:?} is not a directory",
+                    library.dir
+                ));
+            }
+        }
+        if self.host.parse::<IpAddr>().is_err() {
+            return Err(format!(
+                "Invalid `host`: {:?} is not an IP address",
+                self.host
+            ));
+        }
+        let formats = self.libraries.iter().filter_map(|l| l.format.as_ref());
+        for format in std::iter::once(&self.format).chain(formats) {
+            if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!("Invalid `format`: {:?}", format));
+            }
+        }
+        for (field, file) in [("users", &self.users), ("acl", &self.acl)] {
+            if let Some(file) = file {
+                if !Path::new(file).is_file() {
+                    return Err(format!("Invalid `{}`: {:?} does not exist", field, file));
+                }
+            }
+        }
+        if self.auth == AuthMode::Basic && self.users.is_none() {
+            return Err(String::from("Invalid `auth`: basic requires `users`"));
+        }
+
+        Ok(())
+    }
 }
 
-lazy_static! {
-    pub static ref CFG: Config = Config::from_args();
-    pub static ref DIR: &'static Path = Path::new(&CFG.dir);
-    pub static ref BASEPATH: &'static str = CFG.basepath.as_str();
-    pub static ref THUMB_FORMAT: &'static str = CFG.format.as_str();
+// "sub", "/sub" and "sub/" as "/sub/", and "" as "/"
+fn normalize_basepath(basepath: &str) -> String {
+    let segments: Vec<&str> = basepath.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        String::from("/")
+    } else {
+        format!("/{}/", segments.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn load(args: &[&str], file: Option<&str>, env: &[(&str, &str)]) -> Result<Config, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut args: Vec<String> = std::iter::once("hview")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        if let Some(contents) = file {
+            let path = dir.path().join("hview.toml");
+            std::fs::write(&path, contents).unwrap();
+            args.push(String::from("--config"));
+            args.push(path.to_str().unwrap().to_string());
+        }
+
+        let matches = Config::clap().get_matches_from(args);
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        Config::load_from(matches, env)
+    }
+
+    #[test]
+    fn test_layers() {
+        let file = "port = 9000\nread_only = true\nformat = \"webp\"\n\
+                    trusted_proxies = [\"10.0.0.0/8\", \"::1/128\"]\n";
+        let config = load(&["--format", "jpg"], Some(file), &[("HVIEW_PORT", "9001")]).unwrap();
+
+        assert_eq!(config.port, 9001, "env overrides the file");
+        assert_eq!(config.format, "jpg", "flags override the file");
+        assert!(config.read_only);
+        assert_eq!(config.trusted_proxies.len(), 2);
+        assert_eq!(config.host, "0.0.0.0", "defaults are kept");
+
+        let config = load(&["--port", "9002"], None, &[("HVIEW_PORT", "9001")]).unwrap();
+        assert_eq!(config.port, 9002, "flags override env vars");
+    }
+
+    #[test]
+    fn test_switches() {
+        let file = "read_only = true\nno_thumbs = true\n";
+        let config = load(&["--read-only=false"], Some(file), &[]).unwrap();
+        assert!(!config.read_only, "flags turn off switches from the file");
+        assert!(config.no_thumbs);
+
+        let config = load(
+            &["-n=false", "--verbose"],
+            None,
+            &[("HVIEW_NO_THUMBS", "1")],
+        )
+        .unwrap();
+        assert!(!config.no_thumbs, "flags turn off switches from env vars");
+        assert!(config.verbose);
+
+        let config = load(
+            &["--listing-cache", "3"],
+            None,
+            &[("HVIEW_LISTING_CACHE", "5")],
+        );
+        assert_eq!(
+            config.unwrap().listing_cache,
+            3,
+            "multi-word flags override env vars"
+        );
+        assert!(load(&["--read-only=yes"], None, &[])
+            .unwrap_err()
+            .starts_with("Invalid `--read-only`"));
+    }
+
+    #[test]
+    fn test_basepath() {
+        for basepath in &["subdir", "/subdir", "subdir/", "//subdir//"] {
+            let config = load(&["--basepath", basepath], None, &[]).unwrap();
+            assert_eq!(config.basepath, "/subdir/");
+        }
+        let config = load(&[], None, &[("HVIEW_BASEPATH", "")]).unwrap();
+        assert_eq!(config.basepath, "/");
+        let config = load(&["--basepath", "/a/b/"], None, &[]).unwrap();
+        assert_eq!(config.basepath, "/a/b/");
+    }
+
+    #[test]
+    fn test_hash_password() {
+        // Settings that couldn't serve anything don't matter when only hashing
+        let config = load(&["--hash-password"], None, &[("HVIEW_DIR", "/nonexistent")]).unwrap();
+        assert!(config.hash_password);
+        assert!(load(&[], None, &[("HVIEW_DIR", "/nonexistent")]).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |file: Option<&str>, env: &[(&str, &str)]| load(&[], file, env).unwrap_err();
+
+        assert!(error(Some("prot = 80"), &[]).contains("Unknown option `prot`"));
+        assert!(error(Some("port = \"http\""), &[]).starts_with("Invalid `port`"));
+        assert!(error(Some("port = 70000"), &[]).starts_with("Invalid `port`"));
+        assert!(error(None, &[("HVIEW_READ_ONLY", "yes")]).contains("(HVIEW_READ_ONLY)"));
+        assert!(error(None, &[("HVIEW_HOST", "localhost")]).starts_with("Invalid `host`"));
+        assert!(error(None, &[("HVIEW_AUTH", "basic")]).starts_with("Invalid `auth`"));
+        assert!(error(Some("trusted_proxies = [\"nope\"]"), &[])
+            .starts_with("Invalid `trusted_proxies`"));
+        assert!(
+            error(None, &[("HVIEW_LIBRARIES", "_api=./test-fixtures")]).contains("invalid name")
+        );
+        assert!(
+            error(None, &[("HVIEW_LIBRARIES", "a=./test-fixtures;nope")])
+                .contains("unknown library option")
+        );
+    }
+
+    #[test]
+    fn test_libraries() {
+        let file = "[libraries.photos]\ndir = \"./test-fixtures/imgs\"\nread_only = true\n\
+                    [libraries.docs]\ndir = \"./test-fixtures/ext\"\nhidden = \"hide\"\n";
+        let config = load(&[], Some(file), &[]).unwrap();
+        assert_eq!(config.libraries.len(), 2);
+        let docs = &config.libraries[0];
+        assert_eq!(docs.name, "docs");
+        assert_eq!(docs.hidden, Some(Hidden::Hide));
+        assert_eq!(config.libraries[1].read_only, Some(true));
+
+        let config = load(
+            &["--library", "a=./test-fixtures/imgs;format=webp"],
+            None,
+            &[("HVIEW_LIBRARIES", "b=./test-fixtures/ext")],
+        )
+        .unwrap();
+        assert_eq!(config.libraries.len(), 1, "flags override env vars");
+        assert_eq!(config.libraries[0].format.as_deref(), Some("webp"));
+    }
 }
@@ -0,0 +1,524 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, read_dir};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{error::ErrorBadRequest, post, web, Error, HttpResponse};
+use serde::Deserialize;
+
+use crate::acl::{self, Access};
+use crate::auth::Identity;
+use crate::batch::deny_read_only;
+use crate::cache::ListingCache;
+use crate::config::Cover;
+use crate::dir::{DirError, FileEntry, Thumbs};
+use crate::state::AppState;
+
+// Name of the explicit cover in a folder's .th, followed by the thumbnail extension
+const COVER: &str = "cover";
+// An image placed in the folder itself, used as is
+const FOLDER_COVER: &str = "cover.jpg";
+// Name of the generated mosaic in a folder's .th, next to the list of thumbnails it was made of
+const MOSAIC: &str = "mosaic";
+const MOSAIC_TILES: usize = 4;
+// How many levels of sub folders are searched for mosaic tiles
+const MOSAIC_DEPTH: usize = 3;
+
+#[derive(Deserialize, Debug)]
+pub struct CoverRequest {
+    // File whose thumbnail becomes the cover of its folder
+    path: String,
+}
+
+// Cover of `folder` from its own thumbnails: the explicit cover if there is one,
+// otherwise one picked following `thumbs.cover`
+pub fn folder_cover(folder: &Path, thumbs: Thumbs) -> Option<PathBuf> {
+    let th = folder.join(".th");
+    let explicit = th.join(format!("{}.{}", COVER, thumbs.format));
+    if explicit.is_file() {
+        return Some(explicit);
+    }
+    let explicit = folder.join(FOLDER_COVER);
+    if explicit.is_file() {
+        return Some(explicit);
+    }
+
+    match thumbs.cover {
+        Cover::Random => FileEntry::get_random_thumb(&th, thumbs.format),
+        Cover::First => sorted_thumbs(&th, thumbs.format).into_iter().next(),
+        Cover::Newest => sorted_thumbs(&th, thumbs.format)
+            .into_iter()
+            .max_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok()),
+        Cover::Daily => {
+            let sorted = sorted_thumbs(&th, thumbs.format);
+            if sorted.is_empty() {
+                return None;
+            }
+            let index = daily_index(folder, sorted.len(), SystemTime::now());
+            sorted.into_iter().nth(index)
+        }
+        Cover::Mosaic => mosaic(folder, thumbs),
+    }
+}
+
+// Mosaic of `folder` as long as none of the thumbnails it was made of changed,
+// otherwise the first thumbnail of the folder itself, if any
+// Mosaics are checked and made again in the background (unless read-only): looking
+// for the thumbnails they should show walks sub folders
+fn mosaic(folder: &Path, thumbs: Thumbs) -> Option<PathBuf> {
+    if let (false, Some(mosaics)) = (thumbs.read_only, thumbs.mosaics) {
+        mosaics.enqueue(folder, thumbs.format);
+    }
+
+    let (mosaic, list) = mosaic_paths(folder, thumbs.format);
+    if let Ok(listed) = fs::read_to_string(&list) {
+        let tiles: Vec<PathBuf> = listed.lines().map(|t| folder.join(t)).collect();
+        if is_fresh(&mosaic, &list, &listed, &tiles) {
+            return Some(mosaic);
+        }
+    }
+    sorted_thumbs(&folder.join(".th"), thumbs.format)
+        .into_iter()
+        .next()
+}
+
+// The mosaic of a folder, and the list of the thumbnails it was made of
+fn mosaic_paths(folder: &Path, format: &str) -> (PathBuf, PathBuf) {
+    let th = folder.join(".th");
+    (
+        th.join(format!("{}.{}", MOSAIC, format)),
+        th.join(format!("{}.{}.txt", MOSAIC, format)),
+    )
+}
+
+// Mosaics of a library, made by a background thread so listings never wait on montage
+// Listings of the folder and those containing it are made again once its mosaic is
+#[derive(Debug)]
+pub struct Mosaics {
+    listings: Arc<ListingCache>,
+    // Paths of ACL rules (see Acl::rule_paths): what is under one of them inside a
+    // folder may not be readable by everyone who can read the folder and see its mosaic
+    restricted: Arc<Vec<PathBuf>>,
+    state: Arc<Mutex<MosaicState>>,
+    // Started with the first mosaic to check
+    queue: Mutex<Option<Sender<(PathBuf, String)>>>,
+}
+
+#[derive(Debug, Default)]
+struct MosaicState {
+    queued: HashSet<PathBuf>,
+    // Tiles of mosaics that couldn't be made, only tried again once their tiles change
+    failed: HashMap<PathBuf, String>,
+}
+
+impl Mosaics {
+    pub fn new(listings: Arc<ListingCache>, restricted: Arc<Vec<PathBuf>>) -> Self {
+        Self {
+            listings,
+            restricted,
+            state: Arc::new(Mutex::new(MosaicState::default())),
+            queue: Mutex::new(None),
+        }
+    }
+
+    fn enqueue(&self, folder: &Path, format: &str) {
+        if !self.state.lock().unwrap().queued.insert(folder.into()) {
+            return;
+        }
+
+        let mut queue = self.queue.lock().unwrap();
+        let sender = queue.get_or_insert_with(|| {
+            let (sender, receiver) = channel::<(PathBuf, String)>();
+            let listings = self.listings.clone();
+            let restricted = self.restricted.clone();
+            let state = self.state.clone();
+            thread::spawn(move || {
+                for (folder, format) in receiver {
+                    let failed = state.lock().unwrap().failed.get(&folder).cloned();
+                    let made = update_mosaic(&folder, &format, &restricted, failed.as_deref());
+                    let mut state = state.lock().unwrap();
+                    state.queued.remove(&folder);
+                    match made {
+                        Ok(true) => {
+                            state.failed.remove(&folder);
+                            listings.invalidate(&folder);
+                        }
+                        Ok(false) => {}
+                        Err(listed) => {
+                            state.failed.insert(folder, listed);
+                        }
+                    }
+                }
+            });
+            sender
+        });
+        sender.send((folder.to_path_buf(), format.to_owned())).ok();
+    }
+}
+
+// Make the mosaic of `folder` again if it isn't up to date, writing the list of its tiles
+// along with it, and tell whether it changed; a mosaic left with too few tiles is removed
+// The tiles are given back when it can't be made, it isn't tried again while they are
+// the `failed` ones
+fn update_mosaic(
+    folder: &Path,
+    format: &str,
+    restricted: &[PathBuf],
+    failed: Option<&str>,
+) -> Result<bool, String> {
+    let tiles = mosaic_tiles(folder, format, restricted);
+    let (mosaic, list) = mosaic_paths(folder, format);
+    if tiles.len() < 2 {
+        let removed = fs::remove_file(&mosaic).is_ok();
+        let _ = fs::remove_file(&list);
+        return Ok(removed);
+    }
+    let listed = tiles_list(folder, &tiles);
+    if is_fresh(&mosaic, &list, &listed, &tiles) || failed == Some(listed.as_str()) {
+        return Ok(false);
+    }
+    match make_mosaic(&folder.join(".th"), &mosaic, &tiles) {
+        Ok(()) if fs::write(&list, &listed).is_ok() => Ok(true),
+        _ => Err(listed),
+    }
+}
+
+// Up to MOSAIC_TILES thumbnails: those of the folder itself, then the first one
+// of each sub folder (breadth first, in sort order)
+// Files under a `restricted` path inside the folder are left out, so that everyone
+// who can read the folder can read all of its tiles
+fn mosaic_tiles(folder: &Path, format: &str, restricted: &[PathBuf]) -> Vec<PathBuf> {
+    let hidden = |path: &Path| {
+        restricted
+            .iter()
+            .any(|r| r.starts_with(folder) && r != folder && path.starts_with(r))
+    };
+    // The file a thumbnail is of: dir/.th/file.jpg.avif -> dir/file.jpg
+    let shown = |thumb: &PathBuf| match (thumb.parent().and_then(Path::parent), thumb.file_stem()) {
+        (Some(dir), Some(file)) => !hidden(&dir.join(file)),
+        _ => false,
+    };
+    let mut tiles: Vec<PathBuf> = sorted_thumbs(&folder.join(".th"), format)
+        .into_iter()
+        .filter(shown)
+        .take(MOSAIC_TILES)
+        .collect();
+
+    let mut level = vec![folder.to_path_buf()];
+    for _ in 0..MOSAIC_DEPTH {
+        let mut next = vec![];
+        for dir in &level {
+            let mut subdirs: Vec<PathBuf> = match read_dir(dir) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_dir() && !is_dot_file(p) && !hidden(p))
+                    .collect(),
+                Err(_) => continue,
+            };
+            subdirs.sort_by(|a, b| compare_names(a, b));
+            next.extend(subdirs);
+        }
+        for dir in &next {
+            if tiles.len() >= MOSAIC_TILES {
+                return tiles;
+            }
+            if let Some(thumb) = sorted_thumbs(&dir.join(".th"), format)
+                .into_iter()
+                .find(shown)
+            {
+                tiles.push(thumb);
+            }
+        }
+        level = next;
+    }
+    tiles
+}
+
+fn is_dot_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+}
+
+// Tiles of a mosaic relative to its folder, one per line
+fn tiles_list(folder: &Path, tiles: &[PathBuf]) -> String {
+    tiles
+        .iter()
+        .map(|t| {
+            format!(
+                "{}\n",
+                t.strip_prefix(folder).unwrap_or(t).to_string_lossy()
+            )
+        })
+        .collect()
+}
+
+// Whether `mosaic` was made of `listed` tiles, none of which changed since
+fn is_fresh(mosaic: &Path, list: &Path, listed: &str, tiles: &[PathBuf]) -> bool {
+    let made = match fs::metadata(mosaic).and_then(|m| m.modified()) {
+        Ok(made) => made,
+        Err(_) => return false,
+    };
+    fs::read_to_string(list).is_ok_and(|l| l == listed)
+        && tiles.iter().all(|t| {
+            fs::metadata(t)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified <= made)
+        })
+}
+
+// Tile the thumbnails into `mosaic`, through a temporary file so it is never seen half written
+fn make_mosaic(th: &Path, mosaic: &Path, tiles: &[PathBuf]) -> std::io::Result<()> {
+    let suffix = mosaic
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let tmp = tempfile::Builder::new()
+        .prefix(".mosaic")
+        .suffix(&suffix)
+        .tempfile_in(th)?;
+
+    let status = Command::new("montage")
+        .args(tiles)
+        .args([
+            "-tile",
+            "2x2",
+            "-geometry",
+            "256x256+2+2",
+            "-background",
+            "none",
+        ])
+        .arg(tmp.path())
+        .status()?;
+    if !status.success() {
+        return Err(std::io::Error::other("montage failed"));
+    }
+    tmp.persist(mosaic)?;
+    Ok(())
+}
+
+// Thumbnails of `format` in `th`, in the order their files are listed
+fn sorted_thumbs(th: &Path, format: &str) -> Vec<PathBuf> {
+    let mut thumbs: Vec<PathBuf> = match read_dir(th) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == format) && !is_generated(p))
+            .collect(),
+        Err(_) => return vec![],
+    };
+    thumbs.sort_by(|a, b| compare_names(a, b));
+    thumbs
+}
+
+// Explicit covers and mosaics aren't thumbnails of files
+fn is_generated(thumb: &Path) -> bool {
+    thumb
+        .file_stem()
+        .is_some_and(|stem| stem == COVER || stem == MOSAIC)
+}
+
+// Same order as listings: natural sort, ignoring case
+pub fn compare_names(a: &Path, b: &Path) -> Ordering {
+    let name = |p: &Path| {
+        p.file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    };
+    alphanumeric_sort::compare_str(name(a), name(b))
+}
+
+// Index among `len` thumbnails of `folder`, the same for the whole (UTC) day of `now`
+fn daily_index(folder: &Path, len: usize, now: SystemTime) -> usize {
+    let day = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or_default();
+    let mut hasher = DefaultHasher::new();
+    folder.hash(&mut hasher);
+    day.hash(&mut hasher);
+    (hasher.finish() % len as u64) as usize
+}
+
+// Use the thumbnail of a file as the cover of the folder it is in
+#[post("/_api/cover")]
+pub async fn set_cover(
+    state: web::Data<AppState>,
+    req: web::Json<CoverRequest>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let (library, path) = state.resolve(&req.path)?;
+    deny_read_only(library)?;
+    let folder = match path.parent() {
+        Some(folder) if path != library.root && folder.starts_with(&library.root) => folder,
+        _ => return Err(ErrorBadRequest("Invalid path")),
+    };
+    acl::require(&state, identity.as_ref(), library, folder, Access::Write)?;
+
+    let format = library
+        .thumb_format()
+        .ok_or_else(|| ErrorBadRequest("Thumbnails are disabled"))?;
+    let thumb = FileEntry::file_path_to_thumb(&path, format).map_err(ErrorBadRequest)?;
+    if !thumb.is_file() {
+        return Err(ErrorBadRequest("File has no thumbnail"));
+    }
+
+    let cover = folder.join(".th").join(format!("{}.{}", COVER, format));
+    web::block(move || fs::copy(&thumb, &cover).map_err(DirError::from))
+        .await
+        .map_err(DirError::from)?;
+    library.listings.invalidate(folder);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    const ROOT: &str = "./test-fixtures/";
+
+    fn thumbs(cover: Cover) -> Thumbs<'static> {
+        Thumbs {
+            format: "avif",
+            cover,
+            read_only: true,
+            mosaics: None,
+        }
+    }
+
+    #[test]
+    fn test_folder_cover() {
+        let folder = Path::new(ROOT).join("imgs/subimg");
+        assert_eq!(
+            folder_cover(&folder, thumbs(Cover::First)),
+            Some(folder.join(".th/24701-nature-natural-beauty.jpg.avif"))
+        );
+        let daily = folder_cover(&folder, thumbs(Cover::Daily));
+        assert!(daily.is_some());
+        assert_eq!(daily, folder_cover(&folder, thumbs(Cover::Daily)));
+        assert!(folder_cover(&folder, thumbs(Cover::Newest)).is_some());
+        assert!(folder_cover(&Path::new(ROOT).join("ext"), thumbs(Cover::First)).is_none());
+    }
+
+    #[test]
+    fn test_mosaic_tiles() {
+        let root = Path::new(ROOT);
+        let tiles = mosaic_tiles(&root.join("imgs"), "avif", &[]);
+        assert_eq!(tiles.len(), MOSAIC_TILES);
+        assert!(tiles.iter().all(|t| t.starts_with(root.join("imgs/.th"))));
+
+        // Without thumbnails of its own, one of each sub folder
+        let tiles = mosaic_tiles(root, "avif", &[]);
+        assert_eq!(
+            tiles,
+            vec![
+                root.join("imgs/.th/flower.jpg.avif"),
+                root.join("imgs/subimg/.th/24701-nature-natural-beauty.jpg.avif"),
+            ]
+        );
+
+        // Nothing under ACL rules inside the folder, whatever they allow
+        let restricted = [root.join("imgs/subimg"), root.join("imgs/flower.jpg")];
+        let tiles = mosaic_tiles(root, "avif", &restricted);
+        assert_eq!(tiles.len(), 1);
+        assert!(tiles
+            .iter()
+            .all(|t| !t.starts_with(root.join("imgs/subimg")) && !t.ends_with("flower.jpg.avif")));
+        // Rules on the folder itself apply to its tiles just the same
+        let tiles = mosaic_tiles(&root.join("imgs/subimg"), "avif", &restricted);
+        assert!(!tiles.is_empty());
+    }
+
+    #[test]
+    fn test_mosaic() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path();
+        let th = folder.join(".th");
+        fs::create_dir(&th).unwrap();
+        // Not images, so montage fails on them
+        let tiles = vec![th.join("a.jpg.avif"), th.join("b.jpg.avif")];
+        for tile in &tiles {
+            fs::write(tile, "a").unwrap();
+        }
+        let (mosaic_path, list) = mosaic_paths(folder, "avif");
+        let mosaics = Mosaics::new(Arc::new(ListingCache::new(8)), Arc::default());
+        let thumbs = |read_only| Thumbs {
+            read_only,
+            mosaics: Some(&mosaics),
+            ..thumbs(Cover::Mosaic)
+        };
+
+        // Nothing is made in read-only libraries
+        assert_eq!(mosaic(folder, thumbs(true)), Some(tiles[0].clone()));
+        assert!(mosaics.state.lock().unwrap().queued.is_empty());
+
+        // Otherwise the first tile is used while the mosaic is made
+        assert_eq!(mosaic(folder, thumbs(false)), Some(tiles[0].clone()));
+        for _ in 0..100 {
+            if mosaics.state.lock().unwrap().queued.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let state = mosaics.state.lock().unwrap();
+        assert!(state.queued.is_empty());
+        assert!(state.failed.contains_key(folder));
+        drop(state);
+        assert!(!mosaic_path.exists() && !list.exists());
+        // Not tried again until the tiles change
+        let failed = mosaics.state.lock().unwrap().failed.get(folder).cloned();
+        assert_eq!(
+            update_mosaic(folder, "avif", &[], failed.as_deref()),
+            Ok(false)
+        );
+
+        // An up to date mosaic is used as is, without writing its list again
+        fs::write(&mosaic_path, "m").unwrap();
+        fs::write(&list, tiles_list(folder, &tiles)).unwrap();
+        let listed = fs::metadata(&list).unwrap().modified().unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(mosaic(folder, thumbs(false)), Some(mosaic_path));
+        assert_eq!(fs::metadata(&list).unwrap().modified().unwrap(), listed);
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let tile = dir.path().join("a.avif");
+        let mosaic = dir.path().join("mosaic.avif");
+        let list = dir.path().join("mosaic.avif.txt");
+        fs::write(&tile, "a").unwrap();
+        let tiles = vec![tile];
+        let listed = tiles_list(dir.path(), &tiles);
+        assert_eq!(listed, "a.avif\n");
+        assert!(!is_fresh(&mosaic, &list, &listed, &tiles));
+
+        fs::write(&mosaic, "m").unwrap();
+        fs::write(&list, &listed).unwrap();
+        assert!(is_fresh(&mosaic, &list, &listed, &tiles));
+        assert!(!is_fresh(&mosaic, &list, "b.avif\n", &tiles));
+    }
+
+    #[test]
+    fn test_daily_index() {
+        let folder = Path::new("/photos");
+        let day = UNIX_EPOCH + Duration::from_secs(86400 * 19000);
+        let index = daily_index(folder, 10, day);
+        assert!(index < 10);
+        assert_eq!(
+            index,
+            daily_index(folder, 10, day + Duration::from_secs(3600))
+        );
+        assert_eq!(daily_index(folder, 1, day), 0);
+    }
+}
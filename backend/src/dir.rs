@@ -1,45 +1,192 @@
+use std::ffi::{OsStr, OsString};
 use std::fs::read_dir;
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
 use thiserror::Error;
 
+use actix_web::{error::BlockingError, http::StatusCode, HttpResponse, ResponseError};
 use chrono::{TimeZone, Utc};
 use rand::seq::IteratorRandom;
 use serde::{Deserialize, Serialize};
 
-use crate::config::{BASEPATH, CFG, DIR, THUMB_FORMAT};
+use crate::config::Cover;
+use crate::cover::{self, Mosaics};
+use crate::stats::FolderStats;
 
 #[derive(Error, Debug)]
 pub enum DirError {
-    #[error("Directory not found")]
+    #[error("Not found")]
     NotFound,
 
+    #[error("Permission denied")]
+    PermissionDenied,
+
+    #[error("Invalid path")]
+    InvalidPath,
+
+    #[error("Invalid file name: {0}")]
+    InvalidName(String),
+
+    #[error("Path is outside of the served folder")]
+    OutsideRoot,
+
+    #[error("Invalid archive: {0}")]
+    Archive(String),
+
     #[error(transparent)]
-    IOError(#[from] std::io::Error),
+    IOError(std::io::Error),
+}
+
+impl DirError {
+    // Stable identifier of the error, for clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            DirError::NotFound => "not_found",
+            DirError::PermissionDenied => "permission_denied",
+            DirError::InvalidPath => "invalid_path",
+            DirError::InvalidName(_) => "invalid_name",
+            DirError::OutsideRoot => "outside_root",
+            DirError::Archive(_) => "invalid_archive",
+            DirError::IOError(_) => "io_error",
+        }
+    }
+}
+
+impl From<std::io::Error> for DirError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => DirError::NotFound,
+            ErrorKind::PermissionDenied => DirError::PermissionDenied,
+            _ => DirError::IOError(err),
+        }
+    }
+}
+
+impl From<BlockingError<DirError>> for DirError {
+    fn from(err: BlockingError<DirError>) -> Self {
+        match err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => DirError::IOError(std::io::Error::other("Canceled")),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+}
+
+// Errors are sent as JSON, eg {"error": "not_found", "message": "Not found"}
+impl ResponseError for DirError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DirError::NotFound => StatusCode::NOT_FOUND,
+            DirError::PermissionDenied | DirError::OutsideRoot => StatusCode::FORBIDDEN,
+            DirError::InvalidPath => StatusCode::BAD_REQUEST,
+            DirError::InvalidName(_) | DirError::Archive(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            DirError::IOError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.code().to_owned(),
+            message: self.to_string(),
+        })
+    }
+}
+
+// How thumbnails are looked up: their format (also their extension) and how folder covers are picked
+// Mosaic covers are only made with `mosaics`, and never in read-only libraries
+#[derive(Debug, Clone, Copy)]
+pub struct Thumbs<'a> {
+    pub format: &'a str,
+    pub cover: Cover,
+    pub read_only: bool,
+    pub mosaics: Option<&'a Mosaics>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileEntry {
-    name: String,
-    path: PathBuf,
-    size: u64,
-    date: u64,
-    date_string: String,
-    thumb: Option<PathBuf>,
-    ext: Option<String>,
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub date: u64,
+    pub date_string: String,
+    pub thumb: Option<PathBuf>,
+    pub ext: Option<String>,
+    // Totals of folders, once counted (see stats.rs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<FolderStats>,
 }
 
 impl FileEntry {
+    // Generate FileEntry without thumbnail
+    // `path` is relative to the root, `date` is a unix timestamp
+    pub fn new(name: String, path: PathBuf, size: u64, date: u64) -> Self {
+        let date_string = if date > 0 {
+            Utc.timestamp_opt(date as i64, 0)
+                .unwrap()
+                .format("%Y-%m-%d")
+                .to_string()
+        } else {
+            String::from("")
+        };
+
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string());
+
+        Self {
+            name,
+            path,
+            size,
+            date,
+            date_string,
+            thumb: None,
+            ext,
+            stats: None,
+        }
+    }
+
+    // Move the entry under `prefix` (eg, the name of its library)
+    // `prefix` is expected to be a URL path already (see encode_path)
+    pub fn prefix(&mut self, prefix: &Path) {
+        self.path = prefix.join(&self.path);
+        self.thumb = self.thumb.as_ref().map(|t| prefix.join(t));
+    }
+
     // Generate FileEntry struct from DirEntry obbject
     // DirEntry generated by std::fs::read_dir
-    fn from_entry(entry: std::fs::DirEntry) -> Result<Self, DirError> {
+    // No thumbnail is looked up without `thumbs`
+    fn from_entry(
+        root: &Path,
+        entry: std::fs::DirEntry,
+        thumbs: Option<Thumbs>,
+    ) -> Result<Self, DirError> {
         let meta = entry.metadata()?;
-        let name = entry.file_name().into_string().unwrap();
-        let path = entry
-            .path()
-            .strip_prefix(&*DIR)
-            .expect("Valid Path")
-            .to_path_buf();
-        let size = meta.len();
+        Self::from_meta(root, &entry.path(), &meta, thumbs)
+    }
+
+    // Generate FileEntry for a single file or folder inside `root`
+    pub fn stat(root: &Path, path: &Path, thumbs: Option<Thumbs>) -> Result<Self, DirError> {
+        let meta = std::fs::metadata(path)?;
+        Self::from_meta(root, path, &meta, thumbs)
+    }
+
+    // Names that aren't valid UTF-8 are shown lossily, and encoded in `path`
+    fn from_meta(
+        root: &Path,
+        full_path: &Path,
+        meta: &std::fs::Metadata,
+        thumbs: Option<Thumbs>,
+    ) -> Result<Self, DirError> {
+        let relative = full_path.strip_prefix(root).expect("Valid Path");
+        let name = full_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
         let date = if let Ok(date) = meta.modified() {
             date.duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
@@ -47,49 +194,35 @@ impl FileEntry {
         } else {
             0
         };
-        let date_string = if date > 0 {
-            Utc.timestamp(date as i64, 0).format("%Y-%m-%d").to_string()
-        } else {
-            String::from("")
-        };
-
-        let ext = if let Some(ext) = path.extension() {
-            Some(ext.to_str().unwrap().to_string())
-        } else {
-            None
-        };
 
-        let thumb = if meta.is_dir() {
-            Self::get_dir_thumb(&entry.path())
-        } else {
-            Self::get_file_thumb(&entry.path())
+        let mut details = Self::new(name, encode_path(relative)?, meta.len(), date);
+        details.ext = relative
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string());
+        let thumb = match thumbs {
+            None => None,
+            Some(thumbs) if meta.is_dir() => Self::get_dir_thumb(root, full_path, thumbs),
+            Some(thumbs) => Self::get_file_thumb(root, full_path, thumbs.format),
         };
+        details.thumb = thumb.and_then(|t| encode_path(&t).ok());
 
-        Ok(Self {
-            name,
-            path,
-            size,
-            date,
-            date_string,
-            thumb,
-            ext,
-        })
+        Ok(details)
     }
 
-    fn get_dir_thumb(path: &PathBuf) -> Option<PathBuf> {
-        if let Some(th) = Self::get_random_thumb(&path.join(".th")) {
-            Some(th.strip_prefix(&*DIR).ok()?.to_path_buf())
-        } else if let Some(th) = Self::get_sub_dir_thumb(&path) {
-            Some(th.strip_prefix(&*DIR).ok()?.to_path_buf())
+    fn get_dir_thumb(root: &Path, path: &Path, thumbs: Thumbs) -> Option<PathBuf> {
+        if let Some(th) = cover::folder_cover(path, thumbs) {
+            Some(th.strip_prefix(root).ok()?.to_path_buf())
+        } else if let Some(th) = Self::get_sub_dir_thumb(path, thumbs) {
+            Some(th.strip_prefix(root).ok()?.to_path_buf())
         } else {
             None
         }
     }
 
-    fn get_file_thumb(path: &PathBuf) -> Option<PathBuf> {
-        if let Ok(tpath) = Self::file_path_to_thumb(&path) {
+    fn get_file_thumb(root: &Path, path: &Path, format: &str) -> Option<PathBuf> {
+        if let Ok(tpath) = Self::file_path_to_thumb(path, format) {
             if tpath.exists() {
-                Some(tpath.strip_prefix(&*DIR).ok()?.to_path_buf())
+                Some(tpath.strip_prefix(root).ok()?.to_path_buf())
             } else {
                 None
             }
@@ -98,100 +231,209 @@ impl FileEntry {
         }
     }
 
-    // Check 2-levels deep for thumbnail of folder, return first dir found
+    // Check 2-levels deep for thumbnail of folder, return first dir found (in sort order)
     // TODO: This could be more efficient.
-    fn get_sub_dir_thumb(path: &PathBuf) -> Option<PathBuf> {
-        for subentry in read_dir(&path).ok()? {
-            if let Ok(subentry) = subentry {
-                let subpath = subentry.path();
-                if let Some(th) = FileEntry::get_random_thumb(&subpath.join(".th")) {
-                    return Some(th);
-                }
-            }
-        }
-
-        None
+    fn get_sub_dir_thumb(path: &Path, thumbs: Thumbs) -> Option<PathBuf> {
+        let mut subpaths: Vec<PathBuf> = read_dir(path)
+            .ok()?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_dir() && p.file_name() != Some(OsStr::new(".th")))
+            .collect();
+        subpaths.sort_by(|a, b| cover::compare_names(a, b));
+        subpaths
+            .iter()
+            .find_map(|subpath| cover::folder_cover(subpath, thumbs))
     }
 
     // Convert /dir/file.jpg -> /dir/.th/file.jpg.avif
-    pub fn file_path_to_thumb(file: &PathBuf) -> Result<PathBuf, &'static str> {
-        let thumb_name = format!(
-            ".th/{}.{}",
-            file.file_name()
-                .ok_or("Filename")?
-                .to_str()
-                .ok_or("Filename String")?,
-            *THUMB_FORMAT
-        );
-        let file_thumb = file.parent().ok_or("Parent directory")?.join(&thumb_name);
+    pub fn file_path_to_thumb(file: &Path, format: &str) -> Result<PathBuf, &'static str> {
+        let mut thumb_name = file.file_name().ok_or("Filename")?.to_os_string();
+        thumb_name.push(".");
+        thumb_name.push(format);
+        let file_thumb = file
+            .parent()
+            .ok_or("Parent directory")?
+            .join(".th")
+            .join(thumb_name);
 
         Ok(file_thumb)
     }
 
-    // Get file inside dir that ends with THUMB_FORMAT, if exists
-    pub fn get_random_thumb(path: &PathBuf) -> Option<PathBuf> {
+    // Get file inside dir that ends with `format`, if exists
+    pub fn get_random_thumb(path: &Path, format: &str) -> Option<PathBuf> {
         if !path.is_dir() || !path.exists() {
             return None;
         }
 
-        let thumbs = read_dir(&path).ok()?.filter_map(|d| {
+        let thumbs = read_dir(path).ok()?.filter_map(|d| {
             let path = d.ok()?.path();
-            if path.extension()? == *THUMB_FORMAT {
+            if path.extension()? == format {
                 return Some(path);
             }
             None
         });
 
         let mut rng = rand::thread_rng();
-        Some(thumbs.choose(&mut rng)?)
+        thumbs.choose(&mut rng)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Dir {
-    title: String,
-    base_path: &'static str,
-    read_only: bool,
-    files: Vec<FileEntry>,
-    folders: Vec<FileEntry>,
+    pub title: String,
+    // Where paths of the listing are relative to, from where the gallery is mounted:
+    // "/", or the share link the listing is part of
+    pub base_path: String,
+    pub read_only: bool,
+    // Listing is part of a public share link; `download` is false when
+    // the share only allows viewing media
+    pub shared: bool,
+    pub download: bool,
+    pub files: Vec<FileEntry>,
+    pub folders: Vec<FileEntry>,
 }
 
 impl Dir {
-    fn new() -> Self {
+    // `read_only` (and `base_path` for shares) are filled in by the handler serving the listing
+    pub fn new(title: String) -> Self {
         Self {
-            title: String::from(""),
-            base_path: *BASEPATH,
-            read_only: CFG.read_only,
+            title,
+            base_path: String::from("/"),
+            read_only: false,
+            shared: false,
+            download: true,
             files: Vec::new(),
             folders: Vec::new(),
         }
     }
+
+    // Move the listing under `prefix` (eg, the name of its library)
+    pub fn prefix(&mut self, prefix: &Path) {
+        self.title = if self.title.is_empty() {
+            prefix.display().to_string()
+        } else {
+            prefix.join(&self.title).display().to_string()
+        };
+        for entry in self.folders.iter_mut().chain(self.files.iter_mut()) {
+            entry.prefix(prefix);
+        }
+    }
+
+    // Sort properly accounting for any number sequences
+    pub fn sort(&mut self) {
+        self.files.sort_by(|a, b| {
+            alphanumeric_sort::compare_str(a.name.to_lowercase(), b.name.to_lowercase())
+        });
+        self.folders.sort_by(|a, b| {
+            alphanumeric_sort::compare_str(a.name.to_lowercase(), b.name.to_lowercase())
+        });
+    }
+}
+
+// Path components that aren't valid UTF-8 are sent as a token of their raw bytes:
+// NAME_TOKEN followed by the bytes in (URL safe) base64
+const NAME_TOKEN: &str = "~b64~";
+
+// Name of a path component, as used in URLs
+#[cfg(unix)]
+pub fn encode_name(name: &OsStr) -> Result<String, DirError> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(match name.to_str() {
+        Some(name) => name.to_owned(),
+        None => format!(
+            "{}{}",
+            NAME_TOKEN,
+            base64::encode_config(name.as_bytes(), base64::URL_SAFE_NO_PAD)
+        ),
+    })
+}
+
+// Without raw bytes to encode, such names can't be served at all
+#[cfg(not(unix))]
+pub fn encode_name(name: &OsStr) -> Result<String, DirError> {
+    name.to_str()
+        .map(str::to_owned)
+        .ok_or_else(|| DirError::InvalidName(name.to_string_lossy().to_string()))
+}
+
+// Original name of a path component from a URL, see encode_name
+// Tokens of valid UTF-8 can't come from encode_name, so they are taken as a plain name
+// Decoded bytes must be a single component: no "/" or NUL, and not "." or ".."
+pub fn decode_name(name: &str) -> Result<OsString, DirError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let bytes = name
+            .strip_prefix(NAME_TOKEN)
+            .and_then(|token| base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok())
+            .filter(|bytes| std::str::from_utf8(bytes).is_err());
+        if let Some(bytes) = bytes {
+            if bytes.is_empty()
+                || bytes == b"."
+                || bytes == b".."
+                || bytes.iter().any(|&b| b == b'/' || b == 0)
+            {
+                return Err(DirError::InvalidPath);
+            }
+            return Ok(OsString::from_vec(bytes));
+        }
+    }
+    Ok(OsString::from(name))
+}
+
+// Relative path, as used in URLs (see encode_name)
+pub fn encode_path(path: &Path) -> Result<PathBuf, DirError> {
+    path.iter().map(encode_name).collect()
+}
+
+// Resolve a request path (eg, "/imgs/flower.jpg") to a path inside `root`
+// Any component that could escape `root` is rejected
+pub fn resolve_path(root: &Path, file: &str) -> Result<PathBuf, DirError> {
+    let mut path = root.to_path_buf();
+    for component in Path::new(file).components() {
+        match component {
+            Component::Normal(c) => path.push(decode_name(&c.to_string_lossy())?),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => return Err(DirError::OutsideRoot),
+            Component::Prefix(_) => return Err(DirError::InvalidPath),
+        }
+    }
+
+    Ok(path)
 }
 
-// Load DIR details into Dir struct
-pub fn get_dir(dir: &PathBuf) -> Result<Dir, DirError> {
+// Load details of `dir` (inside `root`) into Dir struct
+// Dot files are left out unless `show_hidden`
+pub fn get_dir(
+    root: &Path,
+    dir: &Path,
+    thumbs: Option<Thumbs>,
+    show_hidden: bool,
+) -> Result<Dir, DirError> {
     if !dir.is_dir() {
         return Err(DirError::NotFound);
     }
 
-    let mut page = Dir::new();
     let thpath = dir.join(".th");
-    page.title = dir
-        .strip_prefix(&*DIR)
-        .expect("Valid path title")
-        .display()
-        .to_string();
+    let title = encode_path(dir.strip_prefix(root).expect("Valid path title"))?;
+    let mut page = Dir::new(title.display().to_string());
 
     for entry in read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
 
         // Skip thumbnail dir
-        if path == thpath {
+        if path == thpath || (!show_hidden && entry.file_name().to_string_lossy().starts_with('.'))
+        {
             continue;
         }
 
-        let details = FileEntry::from_entry(entry)?;
+        // Names that can't be encoded are left out (see encode_name)
+        let details = match FileEntry::from_entry(root, entry, thumbs) {
+            Err(DirError::InvalidName(_)) => continue,
+            details => details?,
+        };
 
         // Folders display a random thumbnail from all their files (if available)
         // Files return their individual thumbnail (if available)
@@ -202,14 +444,7 @@ pub fn get_dir(dir: &PathBuf) -> Result<Dir, DirError> {
         }
     }
 
-    // Sort properly accounting for any number sequences
-    page.files.sort_by(|a, b| {
-        alphanumeric_sort::compare_str(a.name.to_lowercase(), b.name.to_lowercase())
-    });
-    page.folders.sort_by(|a, b| {
-        alphanumeric_sort::compare_str(a.name.to_lowercase(), b.name.to_lowercase())
-    });
-
+    page.sort();
     Ok(page)
 }
 
@@ -217,12 +452,20 @@ pub fn get_dir(dir: &PathBuf) -> Result<Dir, DirError> {
 mod test {
     use super::*;
 
+    const ROOT: &str = "./test-fixtures/";
+    const AVIF: Thumbs = Thumbs {
+        format: "avif",
+        cover: Cover::First,
+        read_only: true,
+        mosaics: None,
+    };
+
     #[test]
     fn test_file_path_to_thumb() {
         let init = PathBuf::from("/dir/file.jpg");
         let result = Ok(PathBuf::from("/dir/.th/file.jpg.avif"));
         assert_eq!(
-            FileEntry::file_path_to_thumb(&init),
+            FileEntry::file_path_to_thumb(&init, "avif"),
             result,
             "Basic image path"
         );
@@ -232,7 +475,7 @@ mod test {
             "/Pictures/Special Photos!/file@example.com/.th/video.avi.avif",
         ));
         assert_eq!(
-            FileEntry::file_path_to_thumb(&init),
+            FileEntry::file_path_to_thumb(&init, "avif"),
             result,
             "Uncommon video path"
         );
@@ -242,7 +485,7 @@ mod test {
             "/home/user/Pictures/sub/../.th/base.jpg.avif",
         ));
         assert_eq!(
-            FileEntry::file_path_to_thumb(&init),
+            FileEntry::file_path_to_thumb(&init, "avif"),
             result,
             "Parent in path"
         );
@@ -250,16 +493,120 @@ mod test {
         let init = PathBuf::from("/etc/config_file");
         let result = Ok(PathBuf::from("/etc/.th/config_file.avif"));
         assert_eq!(
-            FileEntry::file_path_to_thumb(&init),
+            FileEntry::file_path_to_thumb(&init, "avif"),
             result,
             "Path without file ext"
         );
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        // Latin-1 names, as left behind by old file shares
+        let root = tempfile::tempdir().unwrap();
+        let folder = root.path().join(OsStr::from_bytes(b"d\xfcr"));
+        let file = folder.join(OsStr::from_bytes(b"caf\xe9.txt"));
+        std::fs::create_dir(&folder).unwrap();
+        std::fs::write(&file, "menu").unwrap();
+
+        let dir = get_dir(root.path(), root.path(), None, true).unwrap();
+        assert_eq!(dir.folders[0].name, "d\u{fffd}r");
+        let dir = get_dir(root.path(), &folder, None, true).unwrap();
+        let entry = &dir.files[0];
+        assert_eq!(entry.name, "caf\u{fffd}.txt");
+        assert_eq!(entry.ext.as_deref(), Some("txt"));
+
+        let url = entry.path.to_str().unwrap();
+        assert_eq!(url, "~b64~ZPxy/~b64~Y2Fm6S50eHQ");
+        assert_eq!(dir.title, "~b64~ZPxy");
+        assert_eq!(resolve_path(root.path(), url).unwrap(), file);
+
+        // Tokens of valid UTF-8 names are names like any other
+        let plain = format!("{}YWJj", NAME_TOKEN);
+        assert_eq!(decode_name(&plain).unwrap(), OsString::from(&plain));
+
+        // Tokens can't smuggle in more than one component
+        let token = |bytes: &[u8]| {
+            format!(
+                "{}{}",
+                NAME_TOKEN,
+                base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+            )
+        };
+        for bytes in [&b"\xff/../../etc"[..], b"/etc/passwd\xff", b"a\xff\0b"] {
+            let url = format!("imgs/{}", token(bytes));
+            assert!(matches!(
+                resolve_path(root.path(), &url),
+                Err(DirError::InvalidPath)
+            ));
+        }
+        assert!(matches!(decode_name(NAME_TOKEN), Ok(name) if name == NAME_TOKEN));
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let root = Path::new(ROOT);
+        assert_eq!(
+            resolve_path(root, "/imgs/flower.jpg").unwrap(),
+            root.join("imgs/flower.jpg")
+        );
+        assert_eq!(resolve_path(root, "").unwrap(), root.to_path_buf());
+        assert!(matches!(
+            resolve_path(root, "imgs/../../etc/passwd"),
+            Err(DirError::OutsideRoot)
+        ));
+
+        let root = root.join("imgs");
+        assert_eq!(
+            resolve_path(&root, "flower.jpg").unwrap(),
+            root.join("flower.jpg")
+        );
+        assert!(resolve_path(&root, "../secret.txt").is_err());
+    }
+
+    #[test]
+    fn test_stat() {
+        let root = Path::new(ROOT);
+        let flower = FileEntry::stat(root, &root.join("imgs/flower.jpg"), Some(AVIF)).unwrap();
+        assert_eq!(flower.name, "flower.jpg");
+        assert_eq!(flower.path, PathBuf::from("imgs/flower.jpg"));
+        assert_eq!(
+            flower.thumb,
+            Some(PathBuf::from("imgs/.th/flower.jpg.avif"))
+        );
+        assert!(FileEntry::stat(root, &root.join("imgs/missing.jpg"), None).is_err());
+    }
+
     #[test]
     fn test_get_random_thumb() {
-        let dir = PathBuf::from(format!("{}imgs/.th", &*DIR.to_str().unwrap()));
-        let thumb = FileEntry::get_random_thumb(&dir);
-        assert!(thumb.is_some());
+        let dir = Path::new(ROOT).join("imgs/.th");
+        assert!(FileEntry::get_random_thumb(&dir, "avif").is_some());
+        assert!(FileEntry::get_random_thumb(&dir, "webp").is_none());
+    }
+
+    #[test]
+    fn test_get_dir() {
+        let root = Path::new(ROOT);
+        let dir = get_dir(root, &root.join("imgs"), Some(AVIF), true).unwrap();
+        assert_eq!(dir.title, "imgs");
+        assert_eq!(dir.folders[0].path, PathBuf::from("imgs/subimg"));
+        let flower = dir.files.iter().find(|f| f.name == "flower.jpg").unwrap();
+        assert_eq!(
+            flower.thumb,
+            Some(PathBuf::from("imgs/.th/flower.jpg.avif"))
+        );
+
+        // Paths are relative to the root the listing was made for
+        let root = root.join("imgs");
+        let mut dir = get_dir(&root, &root, None, true).unwrap();
+        assert_eq!(dir.title, "");
+        assert_eq!(dir.folders[0].path, PathBuf::from("subimg"));
+        assert!(dir.files.iter().all(|f| f.thumb.is_none()));
+
+        dir.prefix(Path::new("photos"));
+        assert_eq!(dir.title, "photos");
+        assert_eq!(dir.folders[0].path, PathBuf::from("photos/subimg"));
     }
 }
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    get, web, Error, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::acl;
+use crate::auth::Identity;
+use crate::check_read;
+use crate::config::Hidden;
+use crate::dir::{FileEntry, Thumbs};
+use crate::jobs::{Job, Jobs};
+use crate::state::AppState;
+
+// Finished scans are answered from memory for this long
+pub(crate) const DUPES_TTL: Duration = Duration::from_secs(5 * 60);
+// Bytes read from the start of files of the same size, before hashing them in full
+const PARTIAL: u64 = 64 * 1024;
+
+#[derive(Deserialize, Debug)]
+pub struct DupesQuery {
+    // Scan again, eg after deleting copies
+    #[serde(default)]
+    refresh: bool,
+}
+
+// Files with the same content
+#[derive(Serialize, Debug, Clone)]
+pub struct DuplicateSet {
+    size: u64,
+    // sha256 of the content, hex encoded
+    hash: String,
+    files: Vec<FileEntry>,
+    #[serde(skip)]
+    full_paths: Vec<PathBuf>,
+}
+
+// Result of a scan, largest sets (by space that can be freed) first
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Dupes {
+    sets: Vec<DuplicateSet>,
+    // Copies can't be deleted in read-only libraries
+    read_only: bool,
+}
+
+// Scans by user (see acl::scan_owner), library and folder, nested library roots
+// share folders
+pub type DupesJobs = Jobs<(Option<String>, String, PathBuf), Dupes>;
+
+// Files under `folder` (thumbnails, what isn't `visible` and, if `show_hidden` is false,
+// dot files left out) grouped by size, then by a hash of their start, then by a hash
+// of their content. Each hashed file counts towards the progress of `job`
+pub fn find_duplicates(
+    folder: &Path,
+    show_hidden: bool,
+    visible: &dyn Fn(&Path, bool) -> bool,
+    job: &Job<Dupes>,
+) -> Vec<(u64, String, Vec<PathBuf>)> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    collect(folder, show_hidden, visible, &mut by_size);
+
+    let mut found = vec![];
+    for (size, paths) in by_size {
+        if paths.len() < 2 {
+            continue;
+        }
+        // Small files are read whole already
+        let partial = if size > PARTIAL {
+            group(paths, |p| hash_file(p, Some(PARTIAL)))
+        } else {
+            vec![paths]
+        };
+        for paths in partial {
+            for (hash, paths) in group_by_hash(paths, job, size) {
+                found.push((size, hash, paths));
+            }
+        }
+    }
+
+    found.sort_by_key(|(size, _, paths)| std::cmp::Reverse(size * (paths.len() as u64 - 1)));
+    found
+}
+
+fn collect(
+    folder: &Path,
+    show_hidden: bool,
+    visible: &dyn Fn(&Path, bool) -> bool,
+    by_size: &mut HashMap<u64, Vec<PathBuf>>,
+) {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == ".th" || (!show_hidden && name.starts_with('.')) {
+            continue;
+        }
+        let file_type = match entry.file_type() {
+            Ok(file_type) if visible(&entry.path(), file_type.is_dir()) => file_type,
+            _ => continue,
+        };
+        match file_type {
+            file_type if file_type.is_dir() => {
+                collect(&entry.path(), show_hidden, visible, by_size)
+            }
+            file_type if file_type.is_file() => {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or_default();
+                // Empty files are all alike, they aren't worth listing
+                if size > 0 {
+                    by_size.entry(size).or_default().push(entry.path());
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Groups of at least two paths with the same key, unreadable files are left out
+fn group<F>(paths: Vec<PathBuf>, key: F) -> Vec<Vec<PathBuf>>
+where
+    F: Fn(&Path) -> io::Result<String>,
+{
+    keyed(paths, key)
+        .into_values()
+        .filter(|p| p.len() > 1)
+        .collect()
+}
+
+fn group_by_hash(paths: Vec<PathBuf>, job: &Job<Dupes>, size: u64) -> Vec<(String, Vec<PathBuf>)> {
+    let hashed = keyed(paths, |p| {
+        let hash = hash_file(p, None);
+        job.add(size);
+        hash
+    });
+    hashed.into_iter().filter(|(_, p)| p.len() > 1).collect()
+}
+
+fn keyed<F>(paths: Vec<PathBuf>, key: F) -> HashMap<String, Vec<PathBuf>>
+where
+    F: Fn(&Path) -> io::Result<String>,
+{
+    let mut keyed: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        if let Ok(key) = key(&path) {
+            keyed.entry(key).or_default().push(path);
+        }
+    }
+    keyed
+}
+
+// sha256 of a file, or of its first `limit` bytes
+fn hash_file(path: &Path, limit: Option<u64>) -> io::Result<String> {
+    let file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    match limit {
+        Some(limit) => io::copy(&mut file.take(limit), &mut hasher)?,
+        None => io::copy(&mut &file, &mut hasher)?,
+    };
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// Sets of files with the same content under a folder, found in the background:
+// answers 202 with the progress until the scan is done, then 200 with the sets
+// Copies are deleted through /_api/batch/delete
+#[get("/_api/duplicates/{file:.*}")]
+pub async fn duplicates(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<DupesQuery>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let (library, path) = state.resolve(req.match_info().query("file"))?;
+    check_read(&state, identity.as_ref(), library, &path)?;
+    if !path.is_dir() {
+        return Err(ErrorBadRequest("Not a folder"));
+    }
+
+    let root = library.root.clone();
+    let name = library.name.clone();
+    let format = library.thumb_format().map(str::to_owned);
+    let cover = library.cover;
+    let show_hidden = library.hidden == Hidden::Show;
+    let read_only = library.read_only;
+    let owner = acl::scan_owner(&state, identity.as_ref());
+    let key = (owner, name.clone(), path.clone());
+    let visible = acl::scan_filter(&state, identity.as_ref(), library);
+    let job = state.dupes.start(key, query.refresh, move |job| {
+        let thumbs = format.as_deref().map(|format| Thumbs {
+            format,
+            cover,
+            read_only,
+            mosaics: None,
+        });
+        let sets = find_duplicates(&path, show_hidden, &visible, job)
+            .into_iter()
+            .map(|(size, hash, paths)| {
+                let (files, full_paths) = paths
+                    .into_iter()
+                    .filter_map(|p| {
+                        let mut entry = FileEntry::stat(&root, &p, thumbs).ok()?;
+                        if !name.is_empty() {
+                            entry.prefix(Path::new(&name));
+                        }
+                        Some((entry, p))
+                    })
+                    .unzip();
+                DuplicateSet {
+                    size,
+                    hash,
+                    files,
+                    full_paths,
+                }
+            })
+            .collect();
+        Dupes { sets, read_only }
+    });
+
+    if job.failed() {
+        return Err(ErrorInternalServerError("Scan failed"));
+    }
+    let mut progress = job.progress();
+    if let Some(dupes) = progress.result.as_mut() {
+        // Copies deleted since aren't shown, nor sets left with a single file
+        for set in dupes.sets.iter_mut() {
+            let exists: Vec<bool> = set.full_paths.iter().map(|p| p.exists()).collect();
+            let mut exists = exists.into_iter();
+            set.files.retain(|_| exists.next().unwrap_or(false));
+        }
+        dupes.sets.retain(|set| set.files.len() > 1);
+        Ok(HttpResponse::Ok().json(progress))
+    } else {
+        Ok(HttpResponse::Accepted().json(progress))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/.th")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        let big: Vec<u8> = (0..PARTIAL * 2).map(|i| (i % 251) as u8).collect();
+        let mut other = big.clone();
+        *other.last_mut().unwrap() ^= 1;
+        fs::write(root.join("a/photo.jpg"), &big).unwrap();
+        fs::write(root.join("b/photo (1).jpg"), &big).unwrap();
+        // Same size and start, different end
+        fs::write(root.join("b/edited.jpg"), &other).unwrap();
+        fs::write(root.join("a/.th/photo.jpg.avif"), &big).unwrap();
+        fs::write(root.join("a/note.txt"), "hello").unwrap();
+        fs::write(root.join("b/note.txt"), "hello").unwrap();
+        fs::write(root.join("b/empty"), "").unwrap();
+        fs::write(root.join("a/empty"), "").unwrap();
+
+        let job = Job::default();
+        let found = find_duplicates(root, false, &|_, _| true, &job);
+        assert_eq!(found.len(), 2);
+        let (size, _, mut paths) = found[0].clone();
+        assert_eq!(size, PARTIAL * 2);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![root.join("a/photo.jpg"), root.join("b/photo (1).jpg")]
+        );
+        assert_eq!(found[1].2.len(), 2);
+        // Three big files and two small ones were hashed in full
+        assert_eq!(job.progress().files, 5);
+
+        // What can't be seen is neither hashed nor counted
+        let job = Job::default();
+        let found = find_duplicates(root, false, &|p, _| !p.ends_with("b"), &job);
+        assert!(found.is_empty());
+        assert_eq!(job.progress().files, 0);
+    }
+}
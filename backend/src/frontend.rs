@@ -0,0 +1,57 @@
+use std::borrow::Cow;
+
+use actix_files::file_extension_to_mime;
+use actix_web::{
+    get,
+    http::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+    web, HttpRequest, HttpResponse,
+};
+use rust_embed::RustEmbed;
+
+// Output of `trunk build --release --public-url <basepath>` in ../frontend,
+// read from disk in debug builds
+#[derive(RustEmbed)]
+#[folder = "../frontend/dist/"]
+struct Dist;
+
+const INDEX: &str = "index.html";
+
+// Register the frontend next to the API (see `mount`)
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(serve);
+}
+
+// Files of the build, and index.html for every other path so the app
+// handles its own routes (like `try_files` in frontend/etc/default.conf.template)
+#[get("/{file:.*}")]
+async fn serve(req: HttpRequest) -> HttpResponse {
+    let file = req.match_info().query("file");
+    let (name, file) = match Dist::get(file) {
+        Some(asset) if !file.is_empty() => (file, asset),
+        _ => match Dist::get(INDEX) {
+            Some(index) => (INDEX, index),
+            None => return HttpResponse::NotFound().body("Frontend not built"),
+        },
+    };
+
+    let etag = format!("\"{}\"", base64::encode(&file.metadata.sha256_hash()[..16]));
+    let tag = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|t| t.to_str().ok());
+    if tag == Some(etag.as_str()) {
+        return HttpResponse::NotModified().finish();
+    }
+
+    let mime = file_extension_to_mime(name.rsplit_once('.').map_or("", |(_, ext)| ext));
+    let body = match file.data {
+        Cow::Borrowed(data) => web::Bytes::from_static(data),
+        Cow::Owned(data) => web::Bytes::from(data),
+    };
+    HttpResponse::Ok()
+        .content_type(mime.to_string())
+        // index.html links to the (hashed) assets of the current build
+        .header(CACHE_CONTROL, "no-cache")
+        .header(ETAG, etag)
+        .body(body)
+}
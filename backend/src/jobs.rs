@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+// Threads running the scans of a kind, scans past that many wait for one to finish
+const WORKERS: usize = 2;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+// A scan running in a background thread, with its progress and its result once done
+#[derive(Debug, Default)]
+pub struct Job<T> {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    // Result (none if the scan panicked) and when the scan finished
+    result: Mutex<Option<(Option<T>, Instant)>>,
+}
+
+// What a scan is at, sent as is while it runs
+#[derive(Serialize, Debug)]
+pub struct Progress<T> {
+    pub done: bool,
+    pub files: u64,
+    pub bytes: u64,
+    #[serde(flatten)]
+    pub result: Option<T>,
+}
+
+impl<T: Clone> Job<T> {
+    // Count a file done, of `bytes`
+    pub fn add(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn progress(&self) -> Progress<T> {
+        let result = self
+            .result
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|(result, _)| result.clone());
+        Progress {
+            done: result.is_some(),
+            files: self.files.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            result,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.result.lock().unwrap().is_some()
+    }
+
+    // Whether the scan panicked; it is run again once expired, or with `refresh`
+    pub fn failed(&self) -> bool {
+        matches!(&*self.result.lock().unwrap(), Some((None, _)))
+    }
+
+    fn expired(&self, ttl: Duration) -> bool {
+        matches!(&*self.result.lock().unwrap(), Some((_, at)) if at.elapsed() > ttl)
+    }
+}
+
+// Scans running, waiting, or finished less than `ttl` ago, so repeated requests share them
+#[derive(Debug)]
+pub struct Jobs<K, T> {
+    ttl: Duration,
+    jobs: Mutex<HashMap<K, Arc<Job<T>>>>,
+    // Started with the first scan
+    queue: Mutex<Option<Sender<Task>>>,
+}
+
+impl<K, T> Jobs<K, T>
+where
+    K: Hash + Eq,
+    T: Clone + Default + Send + 'static,
+{
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            jobs: Mutex::new(HashMap::new()),
+            queue: Mutex::new(None),
+        }
+    }
+
+    // Current job for `key`, queuing `scan` for the workers when there is none
+    // (or, with `refresh`, when the last one is done)
+    pub fn start<F>(&self, key: K, refresh: bool, scan: F) -> Arc<Job<T>>
+    where
+        F: FnOnce(&Job<T>) -> T + Send + 'static,
+    {
+        let mut jobs = self.jobs.lock().unwrap();
+        let ttl = self.ttl;
+        jobs.retain(|_, job| !job.expired(ttl));
+
+        match jobs.get(&key) {
+            Some(job) if !(refresh && job.finished()) => return job.clone(),
+            _ => (),
+        }
+
+        let job = Arc::new(Job::default());
+        jobs.insert(key, job.clone());
+        let running = job.clone();
+        self.run(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| scan(&running))).ok();
+            *running.result.lock().unwrap() = Some((result, Instant::now()));
+        }));
+        job
+    }
+
+    fn run(&self, task: Task) {
+        let mut queue = self.queue.lock().unwrap();
+        let sender = queue.get_or_insert_with(|| {
+            let (sender, receiver) = channel::<Task>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            for _ in 0..WORKERS {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    let task = match receiver.lock().unwrap().recv() {
+                        Ok(task) => task,
+                        Err(_) => return,
+                    };
+                    task();
+                });
+            }
+            sender
+        });
+        sender.send(task).ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_workers() {
+        let jobs: Jobs<usize, u64> = Jobs::new(Duration::from_secs(60));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let started: Vec<Arc<Job<u64>>> = (0..WORKERS * 3)
+            .map(|key| {
+                let (running, most) = (running.clone(), most.clone());
+                jobs.start(key, false, move |job| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    job.add(1);
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    key as u64
+                })
+            })
+            .collect();
+        // Known keys share their job
+        assert!(Arc::ptr_eq(&started[0], &jobs.start(0, false, |_| 0)));
+
+        for _ in 0..100 {
+            if started.iter().all(|job| job.finished()) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        for (key, job) in started.iter().enumerate() {
+            let progress = job.progress();
+            assert_eq!((progress.result, progress.files), (Some(key as u64), 1));
+        }
+        assert!(most.load(Ordering::SeqCst) <= WORKERS);
+    }
+
+    #[test]
+    fn test_panic() {
+        let jobs: Jobs<usize, u64> = Jobs::new(Duration::from_secs(60));
+        let job = jobs.start(0, false, |_| panic!("scan"));
+        for _ in 0..100 {
+            if job.finished() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(job.failed());
+        assert!(!job.progress().done);
+
+        // Shared like any other until refreshed
+        assert!(Arc::ptr_eq(&job, &jobs.start(0, false, |_| 1)));
+        let job = jobs.start(0, true, |_| 1);
+        for _ in 0..100 {
+            if job.finished() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!job.failed());
+        assert_eq!(job.progress().result, Some(1));
+    }
+}
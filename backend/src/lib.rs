@@ -0,0 +1,1060 @@
+//! hview file viewer backend
+//!
+//! The gallery can be mounted into another actix-web app with [`configure`]:
+//!
+//! ```no_run
+//! use actix_web::{web, App, HttpServer};
+//! use hview_backend::{AppState, Config};
+//!
+//! # async fn run(config: Config) -> std::io::Result<()> {
+//! let state = web::Data::new(AppState::new(config).expect("Valid config"));
+//! HttpServer::new(move || {
+//!     let state = state.clone();
+//!     App::new().service(web::scope("/media").configure(|cfg| hview_backend::configure(cfg, state)))
+//! })
+//! .bind("127.0.0.1:8000")?
+//! .run()
+//! .await
+//! # }
+//! ```
+
+pub mod acl;
+pub mod archive;
+pub mod auth;
+mod batch;
+mod cache;
+mod checksum;
+pub mod config;
+mod cover;
+pub mod dir;
+mod dupes;
+#[cfg(feature = "embed-frontend")]
+mod frontend;
+mod jobs;
+pub mod media;
+mod share;
+mod similar;
+pub mod state;
+pub mod stats;
+mod usage;
+
+pub use config::Config;
+pub use dir::{Dir, DirError, FileEntry};
+pub use media::FileStat;
+pub use state::AppState;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use actix_files::{file_extension_to_mime, NamedFile};
+use actix_web::{
+    dev::SizedStream,
+    error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError},
+    get,
+    http::header,
+    web,
+    web::{Bytes, Json},
+    Either, Error, HttpRequest, HttpResponse,
+};
+use futures::channel::{mpsc, oneshot};
+use futures::executor::block_on;
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use acl::Access;
+use archive::ArchiveKind;
+use auth::Identity;
+use config::Hidden;
+use dir::{get_dir, Thumbs};
+use state::Library;
+
+// Files are sent as is, archive members as they are read
+pub type FileResponse = Either<NamedFile, HttpResponse>;
+
+// Archive members are sent in chunks of this size, this many at a time
+const MEMBER_CHUNK_SIZE: usize = 64 * 1024;
+const MEMBER_CHUNKS: usize = 4;
+
+// Register the gallery's endpoints and `state` under `--basepath`, as the binary does
+// With the embedded frontend, the endpoints move to "<basepath>api/" next to it
+pub fn mount(cfg: &mut web::ServiceConfig, state: web::Data<AppState>) {
+    let prefix = state.config.basepath.trim_end_matches('/').to_owned();
+    #[cfg(feature = "embed-frontend")]
+    cfg.service(
+        web::scope(&prefix)
+            .service(web::scope("/api").configure(|cfg| configure(cfg, state)))
+            .configure(frontend::configure),
+    );
+    #[cfg(not(feature = "embed-frontend"))]
+    cfg.service(web::scope(&prefix).configure(|cfg| configure(cfg, state)));
+}
+
+// Register the gallery's endpoints and `state` on an App or Scope
+// Authentication (see auth.rs) only applies to these endpoints
+pub fn configure(cfg: &mut web::ServiceConfig, state: web::Data<AppState>) {
+    cfg.app_data(state).service(
+        web::scope("")
+            .wrap(auth::RequireAuth)
+            .service(auth::login)
+            .service(auth::logout)
+            .service(auth::whoami)
+            .service(libraries)
+            .service(batch::download)
+            .service(batch::delete)
+            .service(batch::move_paths)
+            .service(cover::set_cover)
+            .service(usage::usage)
+            .service(dupes::duplicates)
+            .service(similar::similar)
+            .service(checksum::hash)
+            .service(share::create)
+            .service(share::list)
+            .service(share::revoke)
+            .service(share::unlock)
+            .service(share::pages)
+            .service(share::list_dir)
+            .service(share::file_stat)
+            .service(share::route)
+            .service(pages)
+            .service(list)
+            .service(file_stat)
+            .service(route),
+    );
+}
+
+// A folder listing, a file, or with `Accept: application/json` the details of a file
+#[get("/{file:.*}")]
+async fn route(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<Either<Either<NamedFile, HttpResponse>, Json<FileStat>>, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let file = req.match_info().query("file");
+    if state.named() && file.trim_matches('/').is_empty() {
+        let mut dir = library_dir(&state);
+        acl::filter_dir(&state, &mut dir, identity.as_ref());
+        return Ok(Either::A(Either::B(dir_response(&req, &dir)?)));
+    }
+
+    let (library, path) = state.resolve(file)?;
+    check_read(&state, identity.as_ref(), library, &path)?;
+
+    let as_folder = file.ends_with('/');
+    if wants_json(&req) && !is_folder(&path, as_folder) {
+        return Ok(Either::B(Json(stat_path(library, path).await?)));
+    }
+
+    match open_path(library, path.clone(), as_folder).await? {
+        Either::A(file) => Ok(Either::A(
+            checksum::with_digest(&state, &req, path, file).await?,
+        )),
+        Either::B(mut dir) => {
+            acl::filter_dir(&state, &mut dir, identity.as_ref());
+            Ok(Either::A(Either::B(dir_response(&req, &dir)?)))
+        }
+    }
+}
+
+// Folder listing only, archives included
+#[get("/_api/list/{file:.*}")]
+async fn list(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let file = req.match_info().query("file");
+    let mut dir = if state.named() && file.trim_matches('/').is_empty() {
+        library_dir(&state)
+    } else {
+        let (library, path) = state.resolve(file)?;
+        check_read(&state, identity.as_ref(), library, &path)?;
+        match open_path(library, path, true).await? {
+            Either::A(_) => return Err(ErrorBadRequest("Not a folder")),
+            Either::B(dir) => dir,
+        }
+    };
+
+    acl::filter_dir(&state, &mut dir, identity.as_ref());
+    dir_response(&req, &dir)
+}
+
+// Details of a single file or folder, without its content
+#[get("/_api/stat/{file:.*}")]
+async fn file_stat(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<Json<FileStat>, Error> {
+    let file = req.match_info().query("file");
+    if state.named() && file.trim_matches('/').is_empty() {
+        let root = FileEntry::new(String::new(), PathBuf::new(), 0, 0);
+        return Ok(Json(FileStat::guess(root, true)));
+    }
+
+    let (library, path) = state.resolve(file)?;
+    check_read(&state, identity.as_deref(), library, &path)?;
+    Ok(Json(stat_path(library, path).await?))
+}
+
+// Folders need the list permission, files and archive members need read access
+pub(crate) fn check_read(
+    state: &AppState,
+    identity: Option<&Identity>,
+    library: &Library,
+    path: &Path,
+) -> Result<(), Error> {
+    if path.is_dir() {
+        if !acl::can_list(state, identity, library, path) {
+            return Err(ErrorForbidden("Access denied"));
+        }
+        return Ok(());
+    }
+
+    // Paths inside an archive are checked against the archive itself
+    let target = match archive::split_path(&library.root, path) {
+        Some((archive, _)) if !path.exists() => archive,
+        _ => path.to_owned(),
+    };
+    acl::require(state, identity, library, &target, Access::Read)
+}
+
+// Whether open_path would answer with a listing rather than a file
+// Paths inside archives are assumed to be folders when requested as one
+pub(crate) fn is_folder(path: &Path, as_folder: bool) -> bool {
+    path.is_dir() || (as_folder && (!path.exists() || ArchiveKind::from_path(path).is_some()))
+}
+
+// Whether the client asked for JSON over anything else it accepts
+// Wildcards alone keep the plain file response, so <img> and downloads are unaffected
+pub(crate) fn wants_json(req: &HttpRequest) -> bool {
+    let accept = match req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+    {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    let mut json = None;
+    let mut other: f32 = 0.0;
+    for item in accept.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let mime = parts.next().unwrap_or_default();
+        let quality = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        if mime == "application/json" {
+            json = Some(quality);
+        } else {
+            other = other.max(quality);
+        }
+    }
+
+    matches!(json, Some(q) if q > 0.0 && q >= other)
+}
+
+// Details of a file, folder or archive member, with the path under the library name
+pub async fn stat_path(library: &Library, path: PathBuf) -> Result<FileStat, Error> {
+    let root = library.root.to_owned();
+    let format = library.thumb_format().map(str::to_owned);
+    let (cover, read_only) = (library.cover, library.read_only);
+    let stat = web::block(move || {
+        if path.exists() {
+            // Mosaics are only made for listings
+            let thumbs = format.as_deref().map(|format| Thumbs {
+                format,
+                cover,
+                read_only,
+                mosaics: None,
+            });
+            let entry = FileEntry::stat(&root, &path, thumbs)?;
+            Ok(FileStat::probe(entry, &path))
+        } else if let Some((archive, inner)) = archive::split_path(&root, &path) {
+            archive::stat_member(&root, &archive, &inner)
+        } else {
+            Err(DirError::NotFound)
+        }
+    })
+    .await;
+
+    let mut stat = stat.map_err(DirError::from)?;
+    if !library.name.is_empty() {
+        stat.entry.prefix(Path::new(&library.name));
+    }
+    Ok(stat)
+}
+
+// Listing as JSON, with validators so unchanged listings are answered with a 304
+// The ETag covers the whole body, which also depends on the user (see acl::filter_dir)
+pub(crate) fn dir_response(req: &HttpRequest, dir: &Dir) -> Result<HttpResponse, Error> {
+    let body = serde_json::to_vec(dir)?;
+    let etag = format!("\"{}\"", base64::encode(&Sha256::digest(&body)[..16]));
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|tags| tags.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| tag.trim().trim_start_matches("W/") == etag)
+        });
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::ETAG, etag);
+
+    Ok(if not_modified {
+        response.finish()
+    } else {
+        response.content_type("application/json").body(body)
+    })
+}
+
+// Root listing of named libraries, as folders
+fn library_dir(state: &AppState) -> Dir {
+    let mut dir = Dir::new(String::new());
+    dir.read_only = true;
+    dir.folders = state
+        .libraries
+        .iter()
+        .map(|l| FileEntry::new(l.name.to_owned(), PathBuf::from(&l.name), 0, 0))
+        .collect();
+    dir
+}
+
+#[derive(Serialize, Debug)]
+struct LibraryInfo {
+    name: String,
+    read_only: bool,
+}
+
+// Named libraries the user can browse, for the library picker
+#[get("/_api/libraries")]
+async fn libraries(
+    state: web::Data<AppState>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Json<Vec<LibraryInfo>> {
+    let identity = identity.as_deref();
+    Json(
+        state
+            .libraries
+            .iter()
+            .filter(|l| !l.name.is_empty() && acl::can_list(&state, identity, l, &l.root))
+            .map(|l| LibraryInfo {
+                name: l.name.to_owned(),
+                read_only: l.read_only,
+            })
+            .collect(),
+    )
+}
+
+// Serve a resolved path: a file, a folder listing or a path inside an archive
+// `as_folder` lists archives requested as a folder ("photos.zip/") instead of sending them
+pub async fn open_path(
+    library: &Library,
+    path: PathBuf,
+    as_folder: bool,
+) -> Result<Either<FileResponse, Dir>, Error> {
+    let listing = if let Ok(meta) = std::fs::metadata(&path) {
+        if meta.is_file() {
+            if as_folder && ArchiveKind::from_path(&path).is_some() {
+                archive_route(library, path, PathBuf::new()).await?
+            } else {
+                let file = NamedFile::open(path).map_err(DirError::from)?;
+                return Ok(Either::A(Either::A(file.use_last_modified(true))));
+            }
+        } else {
+            let thumbs = std::fs::metadata(path.join(".th")).and_then(|m| m.modified());
+            let modified = meta.modified().ok().map(|m| (m, thumbs.ok()));
+            let mut dir = match modified.and_then(|m| library.listings.get(&path, m)) {
+                Some(dir) => dir,
+                None => {
+                    let show_hidden = library.hidden == Hidden::Show;
+                    let dir = get_dir(&library.root, &path, library.thumbs(), show_hidden)?;
+                    if let Some(modified) = modified {
+                        library.listings.insert(path, modified, dir.clone());
+                    }
+                    dir
+                }
+            };
+            // Totals change without the folder changing, so they aren't part of cached listings
+            library.folder_stats.fill(&library.root, &mut dir);
+            Either::B(dir)
+        }
+    } else if let Some((archive, inner)) = archive::split_path(&library.root, &path) {
+        archive_route(library, archive, inner).await?
+    } else {
+        return Err(DirError::NotFound.into());
+    };
+
+    // Listings have their paths under the library name
+    Ok(match listing {
+        Either::B(mut dir) => {
+            if !library.name.is_empty() {
+                dir.prefix(Path::new(&library.name));
+            }
+            dir.read_only = library.read_only;
+            Either::B(dir)
+        }
+        file => file,
+    })
+}
+
+// Images of an archive in reading order, for the comic reader
+#[get("/_api/pages/{file:.*}")]
+async fn pages(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<Json<Vec<FileEntry>>, Error> {
+    let (library, path) = state.resolve(req.match_info().query("file"))?;
+    acl::require(&state, identity.as_deref(), library, &path, Access::Read)?;
+
+    let root = library.root.to_owned();
+    let prefix = PathBuf::from(&library.name);
+    let mut pages = web::block(move || archive::get_pages(&root, &path))
+        .await
+        .map_err(DirError::from)?;
+    for page in pages.iter_mut() {
+        page.prefix(&prefix);
+    }
+    Ok(Json(pages))
+}
+
+// Paths inside an archive are either a single member or a virtual folder
+// Access is checked on the archive itself by the caller
+async fn archive_route(
+    library: &Library,
+    archive: PathBuf,
+    inner: PathBuf,
+) -> Result<Either<FileResponse, Dir>, Error> {
+    if !inner.as_os_str().is_empty() {
+        if let Some(member) = stream_member(archive.to_owned(), inner.to_owned()).await? {
+            return Ok(Either::A(Either::B(member)));
+        }
+    }
+
+    let root = library.root.to_owned();
+    let dir = web::block(move || archive::get_archive_dir(&root, &archive, &inner))
+        .await
+        .map_err(DirError::from)?;
+    Ok(Either::B(dir))
+}
+
+// Send the member `inner` of `archive` as it is read, None when it isn't a file of
+// the archive. The archive is read once, on a thread of its own feeding the response:
+// it waits on slow clients, which would hold up other users of the blocking pool
+async fn stream_member(archive: PathBuf, inner: PathBuf) -> Result<Option<HttpResponse>, Error> {
+    let (found_tx, found_rx) = oneshot::channel();
+    let (mut body_tx, body_rx) = mpsc::channel::<io::Result<Bytes>>(MEMBER_CHUNKS);
+    // application/octet-stream for an unknown or missing extension
+    let ext = inner.extension().unwrap_or_default().to_string_lossy();
+    let mime = file_extension_to_mime(&ext);
+
+    let read = move || {
+        let mut found_tx = Some(found_tx);
+        let found = archive::read_member(&archive, &inner, |size, reader| {
+            if let Some(found_tx) = found_tx.take() {
+                let _ = found_tx.send(Ok(Some(size)));
+            }
+            let mut buffer = vec![0; MEMBER_CHUNK_SIZE];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(read) => Ok(Bytes::copy_from_slice(&buffer[..read])),
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+                // Stop once the client is gone
+                if block_on(body_tx.send(chunk)).is_err() || failed {
+                    return Ok(());
+                }
+            }
+        });
+        // Not found, or the archive couldn't be read
+        if let Some(found_tx) = found_tx.take() {
+            let _ = found_tx.send(found.map(|_| None));
+        }
+    };
+    thread::Builder::new()
+        .name(String::from("archive-member"))
+        .spawn(read)
+        .map_err(DirError::from)?;
+
+    match found_rx.await {
+        Ok(Ok(Some(size))) => Ok(Some(
+            HttpResponse::Ok()
+                .content_type(mime.to_string())
+                .body(SizedStream::new(
+                    size,
+                    body_rx.map(|chunk| chunk.map_err(Error::from)),
+                )),
+        )),
+        Ok(Ok(None)) => Ok(None),
+        Ok(Err(err)) => Err(err.into()),
+        Err(_) => Err(ErrorInternalServerError("Could not read archive")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use std::time::Duration;
+    use structopt::StructOpt;
+
+    // Test service for `state`, mounted at the root
+    macro_rules! serve {
+        ($state:expr) => {{
+            let state = $state;
+            test::init_service(App::new().configure(|cfg| configure(cfg, state))).await
+        }};
+    }
+
+    fn state(args: &[&str]) -> web::Data<AppState> {
+        web::Data::new(AppState::new(Config::from_iter(args)).unwrap())
+    }
+
+    // The test fixtures as a single library, without thumbnails
+    fn fixtures() -> web::Data<AppState> {
+        state(&["hview", "-d", "./test-fixtures/", "-n"])
+    }
+
+    #[actix_rt::test]
+    async fn test_mount() {
+        let state = fixtures();
+        let mut app = test::init_service(
+            App::new().service(web::scope("/media").configure(|cfg| configure(cfg, state))),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/media/imgs/").to_request();
+        let dir: Dir = test::read_response_json(&mut app, req).await;
+        assert_eq!(dir.title, "imgs");
+        assert!(!dir.files.is_empty());
+
+        let req = test::TestRequest::get().uri("/imgs/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_errors() {
+        let mut app = serve!(fixtures());
+
+        let req = test::TestRequest::get().uri("/imgs/missing/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: dir::ErrorBody = test::read_body_json(resp).await;
+        assert_eq!(body.error, "not_found");
+
+        let req = test::TestRequest::get()
+            .uri("/_api/stat/imgs/../../Cargo.toml")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: dir::ErrorBody = test::read_body_json(resp).await;
+        assert_eq!(body.error, "outside_root");
+
+        let req = test::TestRequest::get()
+            .uri("/_api/list/archive/unsupported.cbr")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_rt::test]
+    async fn test_not_modified() {
+        let state = fixtures();
+        // Folder totals show up in the listing once counted, changing it
+        let subimg = Path::new("./test-fixtures/imgs/subimg");
+        for _ in 0..100 {
+            if state.libraries[0].folder_stats.get(subimg).is_some() {
+                break;
+            }
+            actix_rt::time::delay_for(Duration::from_millis(20)).await;
+        }
+        assert!(state.libraries[0].folder_stats.get(subimg).is_some());
+        let mut app = serve!(state);
+
+        let req = test::TestRequest::get().uri("/imgs/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get(header::ETAG).unwrap().to_owned();
+        // Listings change with thumbnails and users without the folder changing
+        assert!(resp.headers().get(header::LAST_MODIFIED).is_none());
+
+        let req = test::TestRequest::get()
+            .uri("/imgs/")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get(header::ETAG), Some(&etag));
+
+        let req = test::TestRequest::get()
+            .uri("/imgs/")
+            .header(header::IF_NONE_MATCH, "\"other\"")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[cfg(unix)]
+    #[actix_rt::test]
+    async fn test_non_utf8_route() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        std::fs::write(root.path().join(name), "menu").unwrap();
+        let dir = root.path().to_str().unwrap();
+        let mut app = serve!(state(&["hview", "-d", dir, "-n"]));
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let listing: Dir = test::read_response_json(&mut app, req).await;
+        let path = listing.files[0].path.to_str().unwrap().to_owned();
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/{}", path))
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "menu");
+    }
+
+    #[actix_rt::test]
+    async fn test_folder_named_s() {
+        // Share links are under "_s", a folder named "s" is a folder like any other
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("s")).unwrap();
+        std::fs::write(root.path().join("s/a.txt"), "a").unwrap();
+        let mut app = serve!(state(&["hview", "-d", root.path().to_str().unwrap(), "-n"]));
+
+        let req = test::TestRequest::get().uri("/s/").to_request();
+        let dir: Dir = test::read_response_json(&mut app, req).await;
+        assert_eq!(dir.files[0].name, "a.txt");
+        let req = test::TestRequest::get()
+            .uri("/_api/stat/s/a.txt")
+            .to_request();
+        let stat: FileStat = test::read_response_json(&mut app, req).await;
+        assert_eq!(stat.entry.size, 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_batch() {
+        let (one, two, three) = (
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+            tempfile::tempdir().unwrap(),
+        );
+        std::fs::create_dir_all(one.path().join(".th")).unwrap();
+        std::fs::create_dir_all(one.path().join("sub")).unwrap();
+        std::fs::write(one.path().join("x.jpg"), "x").unwrap();
+        std::fs::write(one.path().join(".th/x.jpg.avif"), "thumb").unwrap();
+        std::fs::write(one.path().join("y.txt"), "y").unwrap();
+        std::fs::write(three.path().join("r.txt"), "r").unwrap();
+        let spec = |name: &str, dir: &tempfile::TempDir| {
+            format!("{}={}", name, dir.path().to_str().unwrap())
+        };
+        let (one_spec, two_spec) = (spec("one", &one), spec("two", &two));
+        let three_spec = format!("{};read_only=true", spec("three", &three));
+        let mut app = serve!(state(&[
+            "hview",
+            "--library",
+            &one_spec,
+            "--library",
+            &two_spec,
+            "--library",
+            &three_spec,
+            "-n",
+        ]));
+
+        // Thumbnails move along with their file
+        let req = test::TestRequest::post()
+            .uri("/_api/batch/move")
+            .set_json(&serde_json::json!({"paths": ["one/x.jpg"], "dest": "one/sub"}))
+            .to_request();
+        let result: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(result["done"], serde_json::json!(["one/x.jpg"]));
+        assert!(one.path().join("sub/x.jpg").exists());
+        assert!(one.path().join("sub/.th/x.jpg.avif").exists());
+        assert!(!one.path().join(".th/x.jpg.avif").exists());
+
+        let req = test::TestRequest::post()
+            .uri("/_api/batch/move")
+            .set_json(&serde_json::json!({"paths": ["one/y.txt"], "dest": "two"}))
+            .to_request();
+        let result: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(result["failed"][0]["path"], "one/y.txt");
+        assert!(one.path().join("y.txt").exists());
+
+        let req = test::TestRequest::post()
+            .uri("/_api/batch/delete")
+            .set_json(&serde_json::json!({"paths": ["one/sub/x.jpg"]}))
+            .to_request();
+        let result: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(result["done"], serde_json::json!(["one/sub/x.jpg"]));
+        assert!(!one.path().join("sub/x.jpg").exists());
+        assert!(!one.path().join("sub/.th/x.jpg.avif").exists());
+
+        // Nothing changes in read-only libraries
+        for (uri, body) in &[
+            (
+                "/_api/batch/delete",
+                serde_json::json!({"paths": ["three/r.txt"]}),
+            ),
+            (
+                "/_api/batch/move",
+                serde_json::json!({"paths": ["three/r.txt"], "dest": "three"}),
+            ),
+            (
+                "/_api/batch/move",
+                serde_json::json!({"paths": ["one/y.txt"], "dest": "three"}),
+            ),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+        assert!(three.path().join("r.txt").exists());
+        assert!(one.path().join("y.txt").exists());
+    }
+
+    #[actix_rt::test]
+    async fn test_batch_acl() {
+        // Folders can't be moved or deleted with a subfolder the user can't change
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("docs/hr")).unwrap();
+        std::fs::create_dir_all(root.path().join("public")).unwrap();
+        std::fs::write(root.path().join("docs/hr/pay.txt"), "pay").unwrap();
+        std::fs::write(root.path().join("docs/memo.txt"), "memo").unwrap();
+        let acl = root.path().join("public/acl");
+        std::fs::write(
+            &acl,
+            "* / read\n* docs write\n* public write\n* docs/hr none\n",
+        )
+        .unwrap();
+        let mut app = serve!(state(&[
+            "hview",
+            "-d",
+            root.path().to_str().unwrap(),
+            "--acl",
+            acl.to_str().unwrap(),
+            "-n",
+        ]));
+
+        for (uri, body) in &[
+            ("/_api/batch/delete", serde_json::json!({"paths": ["docs"]})),
+            (
+                "/_api/batch/move",
+                serde_json::json!({"paths": ["docs"], "dest": "public"}),
+            ),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", uri);
+        }
+        assert!(root.path().join("docs/hr/pay.txt").exists());
+
+        // What is left in the folder can still be changed
+        let req = test::TestRequest::post()
+            .uri("/_api/batch/move")
+            .set_json(&serde_json::json!({"paths": ["docs/memo.txt"], "dest": "public"}))
+            .to_request();
+        let result: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(result["done"], serde_json::json!(["docs/memo.txt"]));
+    }
+
+    #[actix_rt::test]
+    async fn test_basic_auth() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = dir.path().join("users");
+        let hash = auth::hash_password("secret").unwrap();
+        std::fs::write(&users, format!("alice:{}\n", hash)).unwrap();
+        let state = state(&[
+            "hview",
+            "-d",
+            "./test-fixtures/",
+            "--users",
+            users.to_str().unwrap(),
+            "--auth",
+            "basic",
+            "-n",
+        ]);
+        let mut app = serve!(state.clone());
+
+        use actix_web::dev::Service;
+        let basic = |credentials: &str| format!("Basic {}", base64::encode(credentials));
+        for (credentials, status) in &[
+            ("alice:secret", StatusCode::OK),
+            ("alice:wrong", StatusCode::UNAUTHORIZED),
+            ("alice:wrong", StatusCode::UNAUTHORIZED),
+        ] {
+            let req = test::TestRequest::get()
+                .uri("/_api/whoami")
+                .header(header::AUTHORIZATION, basic(credentials))
+                .to_request();
+            // Refused before reaching the app, as an error
+            let got = match app.call(req).await {
+                Ok(resp) => resp.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            assert_eq!(got, *status, "{}", credentials);
+        }
+        // Failures are remembered as well, so they aren't checked again
+        let cache = state.basic_cache.read().unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.values().filter(|known| known.is_none()).count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_nested_acl() {
+        // The same folder is readable through one library and not the other
+        let dir = tempfile::tempdir().unwrap();
+        let acl = dir.path().join("acl");
+        std::fs::write(&acl, "* all read\n* photos none\n").unwrap();
+        let mut app = serve!(state(&[
+            "hview",
+            "--library",
+            "photos=./test-fixtures/imgs",
+            "--library",
+            "all=./test-fixtures",
+            "--acl",
+            acl.to_str().unwrap(),
+            "-n",
+        ]));
+
+        let req = test::TestRequest::get().uri("/all/imgs/").to_request();
+        let dir: Dir = test::read_response_json(&mut app, req).await;
+        assert!(dir.files.iter().any(|file| file.name == "flower.jpg"));
+        for (uri, status) in &[
+            ("/all/imgs/flower.jpg", StatusCode::OK),
+            ("/_api/stat/all/imgs/flower.jpg", StatusCode::OK),
+            ("/photos/flower.jpg", StatusCode::FORBIDDEN),
+            ("/photos/", StatusCode::FORBIDDEN),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status, "{}", uri);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_usage() {
+        let mut app = serve!(fixtures());
+
+        // Answered with the progress until the scan is done
+        let mut report = serde_json::Value::Null;
+        for _ in 0..100 {
+            let req = test::TestRequest::get()
+                .uri("/_api/usage/imgs?depth=1&top=2")
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            let status = resp.status();
+            report = test::read_body_json(resp).await;
+            if status == StatusCode::OK {
+                break;
+            }
+            assert_eq!(status, StatusCode::ACCEPTED);
+            actix_rt::time::delay_for(Duration::from_millis(20)).await;
+        }
+        assert_eq!(report["done"], true);
+        assert_eq!(report["tree"]["path"], "imgs");
+        assert_eq!(report["tree"]["children"].as_array().unwrap().len(), 2);
+
+        let req = test::TestRequest::get()
+            .uri("/_api/usage/imgs/flower.jpg")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_similar() {
+        let mut app = serve!(fixtures());
+
+        // Only images are compared
+        let req = test::TestRequest::get()
+            .uri("/_api/similar/imgs")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let req = test::TestRequest::get()
+            .uri("/_api/similar/ext/somefile.pdf")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Closest images first, with hashes saved by an earlier index
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join(".th")).unwrap();
+        let mut saved = String::new();
+        for (name, hash) in [
+            ("photo.jpg", 0u64),
+            ("far.jpg", 0xffff_ffff_ff00),
+            ("close.jpg", 0b1),
+            ("closer.jpg", 0b111),
+        ] {
+            let path = root.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+            let secs = modified.duration_since(std::time::UNIX_EPOCH).unwrap();
+            let line = format!(
+                "{:016x}\t{}\t{}\t{}\n",
+                hash,
+                name.len(),
+                secs.as_secs(),
+                name
+            );
+            saved.push_str(&line);
+        }
+        std::fs::write(root.path().join(".th/dhash.txt"), saved).unwrap();
+        let dir = root.path().to_str().unwrap();
+        let mut app = serve!(state(&["hview", "-d", dir, "-n"]));
+
+        let mut report = serde_json::Value::Null;
+        for _ in 0..100 {
+            let req = test::TestRequest::get()
+                .uri("/_api/similar/photo.jpg")
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            let status = resp.status();
+            report = test::read_body_json(resp).await;
+            if status == StatusCode::OK {
+                break;
+            }
+            assert_eq!(status, StatusCode::ACCEPTED);
+            actix_rt::time::delay_for(Duration::from_millis(20)).await;
+        }
+        assert_eq!(report["done"], true);
+        assert_eq!(report["hash"], "0000000000000000");
+        let similar = report["similar"].as_array().unwrap();
+        let names: Vec<_> = similar.iter().map(|s| &s["file"]["name"]).collect();
+        assert_eq!(names, ["close.jpg", "closer.jpg"]);
+        assert_eq!(similar[0]["distance"], 1);
+        assert_eq!(similar[1]["distance"], 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_hash() {
+        let mut app = serve!(fixtures());
+
+        let req = test::TestRequest::get()
+            .uri("/_api/hash/ext/somefile.blank?algo=md5")
+            .to_request();
+        let checksum: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(checksum["algo"], "md5");
+        assert_eq!(checksum["digest"], "d41d8cd98f00b204e9800998ecf8427e");
+
+        for uri in &["/_api/hash/ext/somefile.blank?algo=crc32", "/_api/hash/ext"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Digests of downloads only when asked for
+        let req = test::TestRequest::get()
+            .uri("/ext/somefile.blank")
+            .header("Want-Repr-Digest", "sha-256=10")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(
+            resp.headers().get("Repr-Digest").unwrap(),
+            "sha-256=:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=:"
+        );
+        assert!(resp.headers().get("Digest").is_none());
+        let req = test::TestRequest::get()
+            .uri("/ext/somefile.blank")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.headers().get("Repr-Digest").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_negotiation() {
+        let mut app = serve!(fixtures());
+
+        let req = test::TestRequest::get()
+            .uri("/imgs/flower.jpg")
+            .header(header::ACCEPT, "application/json")
+            .to_request();
+        let stat: FileStat = test::read_response_json(&mut app, req).await;
+        assert_eq!(stat.entry.name, "flower.jpg");
+        assert_eq!(stat.kind, media::Kind::Image);
+
+        let req = test::TestRequest::get()
+            .uri("/imgs/flower.jpg")
+            .header(header::ACCEPT, "image/*,application/json;q=0.5")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/jpeg"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/_api/stat/archive/album.zip/inner/b.txt")
+            .to_request();
+        let stat: FileStat = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            stat.entry.path,
+            PathBuf::from("archive/album.zip/inner/b.txt")
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/_api/list/archive/album.zip")
+            .to_request();
+        let dir: Dir = test::read_response_json(&mut app, req).await;
+        assert!(!dir.files.is_empty());
+
+        let req = test::TestRequest::get()
+            .uri("/_api/list/imgs/flower.jpg")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_archive_members() {
+        let mut app = serve!(fixtures());
+
+        for archive in &["album.zip", "album.tar.gz"] {
+            let uri = format!("/archive/{}/inner/deeper/c.txt", archive);
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/plain"
+            );
+            assert_eq!(test::read_body(resp).await, "c\n");
+
+            // Folders of the archive are listed
+            let uri = format!("/archive/{}/inner/", archive);
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let dir: Dir = test::read_response_json(&mut app, req).await;
+            assert!(dir.folders.iter().any(|folder| folder.name == "deeper"));
+
+            let uri = format!("/archive/{}/inner/missing.txt", archive);
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
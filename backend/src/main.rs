@@ -1,69 +1,68 @@
-mod config;
-use config::{CFG, DIR};
-
-mod dir;
-use dir::{get_dir, Dir};
-
 use std::net::IpAddr;
-use std::path::PathBuf;
-use std::time::Instant;
 
-use actix_files::NamedFile;
-use actix_web::{
-    error::ErrorNotFound, get, middleware, web::Json, App, Either, Error, HttpRequest, HttpServer,
-};
+use actix_web::{middleware, web, App, HttpServer};
 
-#[get("/{file:.*}")]
-async fn route(req: HttpRequest) -> Result<Either<NamedFile, Json<Dir>>, Error> {
-    let file: PathBuf = req.match_info().query("file").parse().unwrap();
-    let safe_file: PathBuf = if let Ok(strip) = file.strip_prefix("/") {
-        strip.to_path_buf()
-    } else {
-        file
-    };
-    let path: PathBuf = DIR.join(safe_file);
-
-    if let Ok(meta) = std::fs::metadata(&path) {
-        if meta.is_file() {
-            if let Ok(file) = NamedFile::open(path) {
-                Ok(Either::A(file.use_last_modified(true)))
-            } else {
-                Err(ErrorNotFound("File Not Found"))
-            }
-        } else {
-            // Temporary: profile this function call
-            let now = Instant::now();
-            if let Ok(dir) = get_dir(&path) {
-                println!("Time elapsed {}s", now.elapsed().as_secs_f64());
-                Ok(Either::B(Json(dir)))
-            } else {
-                Err(ErrorNotFound("DIR Not Found"))
-            }
-        }
-    } else {
-        Err(ErrorNotFound("Path Not Found"))
-    }
-}
+use hview_backend::config::AuthMode;
+use hview_backend::{auth, AppState, Config};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(2);
+    });
+
+    let level = if config.verbose { "debug" } else { "info" };
+    std::env::set_var("RUST_LOG", format!("actix_web={}", level));
     env_logger::init();
 
-    let host = CFG
+    if config.hash_password {
+        let mut password = String::new();
+        std::io::stdin().read_line(&mut password)?;
+        let hash = auth::hash_password(password.trim_end_matches(&['\r', '\n'][..]))
+            .expect("Could not hash password");
+        println!("{}", hash);
+        return Ok(());
+    }
+
+    let host = config
         .host
         .parse::<IpAddr>()
         .expect("Invalid bind IP configured");
-    let port = &CFG.port;
-    let binder = format!("{}:{}", &host, &port);
+    let binder = format!("{}:{}", &host, &config.port);
+
+    println!("Starting hview-backend @ {}{}", &binder, &config.basepath);
+    if config.libraries.is_empty() {
+        println!("Serving dir: {:?}", &config.dir);
+    }
+    for library in &config.libraries {
+        println!("Serving library {}: {:?}", library.name, library.dir);
+    }
+    if let Some(users) = &config.users {
+        println!("Authentication enabled, users: {:?}", users);
+    }
+    if let AuthMode::Header(header) = &config.auth {
+        println!(
+            "Authentication by {:?} header from {:?}",
+            header, config.trusted_proxies
+        );
+    }
+    if let Some(acl) = &config.acl {
+        println!("Access control list: {:?}", acl);
+    }
+    if let Some(shares) = &config.shares {
+        println!("Share links: {:?}", shares);
+    }
 
-    println!("Starting hview-backend @ {}", &binder);
-    println!("Serving dir: {:?}", &*DIR);
+    let state = web::Data::new(AppState::new(config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }));
 
-    HttpServer::new(|| {
+    HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
-            .service(route)
+            .configure(|cfg| hview_backend::mount(cfg, state.clone()))
     })
     .bind(binder)?
     .run()
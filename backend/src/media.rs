@@ -0,0 +1,190 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix_files::file_extension_to_mime;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::ArchiveKind;
+use crate::dir::FileEntry;
+
+// ffprobe is stopped past this, eg on a slow network mount, and the details left out
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// What the frontend should do with an entry
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Folder,
+    Image,
+    Video,
+    Audio,
+    Text,
+    Archive,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+// FileEntry extended with what is known about its content
+// Dimensions and duration are only filled in when they could be read
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileStat {
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    pub mime: Option<String>,
+    pub kind: Kind,
+    pub dimensions: Option<Dimensions>,
+    // Seconds
+    pub duration: Option<f64>,
+}
+
+impl FileStat {
+    // Details guessed from the name alone, eg for members of an archive
+    pub fn guess(entry: FileEntry, is_dir: bool) -> Self {
+        let mime = match &entry.ext {
+            Some(ext) if !is_dir => Some(file_extension_to_mime(ext)),
+            _ => None,
+        };
+        let kind = if is_dir {
+            Kind::Folder
+        } else if ArchiveKind::from_path(&entry.path).is_some() {
+            Kind::Archive
+        } else {
+            match mime.as_ref().map(|m| m.type_().as_str()) {
+                Some("image") => Kind::Image,
+                Some("video") => Kind::Video,
+                Some("audio") => Kind::Audio,
+                Some("text") => Kind::Text,
+                _ => Kind::Other,
+            }
+        };
+
+        Self {
+            entry,
+            mime: mime.map(|m| m.to_string()),
+            kind,
+            dimensions: None,
+            duration: None,
+        }
+    }
+
+    // Details of `file` on disk, reading the headers of images and videos
+    pub fn probe(entry: FileEntry, file: &Path) -> Self {
+        let mut stat = Self::guess(entry, file.is_dir());
+        match stat.kind {
+            Kind::Image => {
+                stat.dimensions = imagesize::size(file).ok().map(|size| Dimensions {
+                    width: size.width as u32,
+                    height: size.height as u32,
+                })
+            }
+            Kind::Video | Kind::Audio => ffprobe(file, &mut stat),
+            _ => (),
+        }
+        stat
+    }
+}
+
+// Dimensions and duration of a video or audio file, when ffprobe is installed
+fn ffprobe(file: &Path, stat: &mut FileStat) {
+    let mut command = Command::new("ffprobe");
+    command
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height:format=duration"])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(file);
+    let output = match output_within(&mut command, PROBE_TIMEOUT) {
+        Ok(output) if output.status.success() => output,
+        _ => return,
+    };
+
+    let (mut width, mut height) = (None, None);
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match line.split_once('=') {
+            Some(("width", value)) => width = value.parse().ok(),
+            Some(("height", value)) => height = value.parse().ok(),
+            Some(("duration", value)) => stat.duration = value.parse().ok(),
+            _ => (),
+        }
+    }
+    if let (Some(width), Some(height)) = (width, height) {
+        stat.dimensions = Some(Dimensions { width, height });
+    }
+}
+
+// Output of a command, killed when it runs for longer than `timeout`
+// Only suited to commands printing little, stdout is read once they exit
+fn output_within(command: &mut Command, timeout: Duration) -> io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let mut stdout = vec![];
+    if let Some(mut out) = child.stdout.take() {
+        out.read_to_end(&mut stdout)?;
+    }
+    Ok(Output {
+        status,
+        stdout,
+        stderr: vec![],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    const ROOT: &str = "./test-fixtures/";
+
+    #[test]
+    fn test_probe() {
+        let root = Path::new(ROOT);
+        let file = root.join("imgs/flower.jpg");
+        let entry = FileEntry::stat(root, &file, None).unwrap();
+        let stat = FileStat::probe(entry, &file);
+        assert_eq!(stat.kind, Kind::Image);
+        assert_eq!(stat.mime.as_deref(), Some("image/jpeg"));
+        assert!(stat.dimensions.is_some());
+
+        let entry = FileEntry::new("b.zip".to_owned(), PathBuf::from("a/b.zip"), 0, 0);
+        assert_eq!(FileStat::guess(entry, false).kind, Kind::Archive);
+        let entry = FileEntry::new("a".to_owned(), PathBuf::from("a"), 0, 0);
+        let folder = FileStat::guess(entry, true);
+        assert_eq!(folder.kind, Kind::Folder);
+        assert_eq!(folder.mime, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_within() {
+        let output = output_within(Command::new("echo").arg("done"), PROBE_TIMEOUT).unwrap();
+        assert_eq!(output.stdout, b"done\n");
+
+        let start = Instant::now();
+        let slow = output_within(Command::new("sleep").arg("10"), Duration::from_millis(100));
+        assert_eq!(slow.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_files::{file_extension_to_mime, NamedFile};
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    delete,
+    error::{
+        ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
+    get, post, web,
+    web::Json,
+    Either, Error, HttpMessage, HttpRequest, HttpResponse,
+};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::acl::{self, Access};
+use crate::auth::{self, Identity};
+use crate::checksum::with_digest;
+use crate::dir::{encode_path, Dir, DirError, FileEntry};
+use crate::media::FileStat;
+use crate::state::{AppState, Library};
+use crate::{archive, dir_response, is_folder, open_path, stat_path, wants_json};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Cookie set once the password of share "<id>" was entered
+const UNLOCK_COOKIE: &str = "hview_share_";
+
+// A folder shared through a public link
+// `path` is relative to the root, times are unix timestamps
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Share {
+    pub id: String,
+    pub path: PathBuf,
+    pub created: u64,
+    pub expires: Option<u64>,
+    // argon2 hash of the share password
+    pub password: Option<String>,
+    pub download: bool,
+    pub owner: Option<String>,
+}
+
+impl Share {
+    fn expired(&self, now: u64) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+}
+
+// Share details returned by the API, without the password hash
+#[derive(Serialize, Debug)]
+pub struct ShareInfo {
+    id: String,
+    token: String,
+    path: PathBuf,
+    created: u64,
+    expires: Option<u64>,
+    protected: bool,
+    download: bool,
+    owner: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SharesFile {
+    secret: String,
+    shares: Vec<Share>,
+}
+
+// Share links, optionally persisted to a JSON file
+// Tokens are "<id>.<signature>", signed with a secret that is kept
+// alongside the shares (or generated on each start without a file)
+pub struct Shares {
+    secret: Vec<u8>,
+    shares: RwLock<HashMap<String, Share>>,
+    file: Option<PathBuf>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+impl Shares {
+    pub fn load(file: Option<&str>) -> std::io::Result<Self> {
+        let file = file.map(PathBuf::from);
+        let contents = match &file {
+            Some(file) if file.exists() => serde_json::from_str(&std::fs::read_to_string(file)?)?,
+            _ => SharesFile::default(),
+        };
+
+        let secret = if contents.secret.is_empty() {
+            random_string(64)
+        } else {
+            contents.secret
+        };
+        let shares = Self {
+            secret: secret.into_bytes(),
+            shares: RwLock::new(
+                contents
+                    .shares
+                    .into_iter()
+                    .map(|s| (s.id.to_owned(), s))
+                    .collect(),
+            ),
+            file,
+        };
+        shares.save(&shares.shares.read().unwrap())?;
+
+        Ok(shares)
+    }
+
+    // Callers hold the lock on `shares`, so saves don't interleave, and the file is
+    // replaced at once by a complete temp file
+    fn save(&self, shares: &HashMap<String, Share>) -> std::io::Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let contents = SharesFile {
+            secret: String::from_utf8_lossy(&self.secret).to_string(),
+            shares: shares.values().cloned().collect(),
+        };
+        let dir = file
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        // Only readable by the server's user (as temp files are), it holds the secret
+        // signing links
+        let mut out = tempfile::NamedTempFile::new_in(dir)?;
+        out.write_all(serde_json::to_string_pretty(&contents)?.as_bytes())?;
+        out.persist(file).map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    fn mac(&self, message: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key size");
+        mac.update(message.as_bytes());
+        mac
+    }
+
+    // The signature covers everything that decides what a link exposes
+    fn signed(share: &Share) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            share.id,
+            share.path.display(),
+            share.expires.unwrap_or(0),
+            share.download
+        )
+    }
+
+    pub fn token(&self, share: &Share) -> String {
+        let signature = self.mac(&Self::signed(share)).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            share.id,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    pub fn create(&self, mut share: Share) -> std::io::Result<Share> {
+        share.id = random_string(16);
+        let mut shares = self.shares.write().unwrap();
+        let now = now();
+        shares.retain(|_, s| !s.expired(now));
+        shares.insert(share.id.to_owned(), share.to_owned());
+        self.save(&shares)?;
+
+        Ok(share)
+    }
+
+    // Share behind a token, if the signature matches and it has not expired or been revoked
+    pub fn verify(&self, token: &str) -> Option<Share> {
+        let (id, signature) = token.split_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        let share = self.shares.read().unwrap().get(id)?.to_owned();
+
+        self.mac(&Self::signed(&share))
+            .verify_slice(&signature)
+            .ok()?;
+        if share.expired(now()) {
+            return None;
+        }
+
+        Some(share)
+    }
+
+    pub fn list(&self, owner: Option<&str>) -> Vec<Share> {
+        let now = now();
+        let mut shares: Vec<Share> = self
+            .shares
+            .read()
+            .unwrap()
+            .values()
+            .filter(|s| !s.expired(now) && (owner.is_none() || s.owner.as_deref() == owner))
+            .cloned()
+            .collect();
+        shares.sort_by_key(|s| s.created);
+        shares
+    }
+
+    pub fn get(&self, id: &str) -> Option<Share> {
+        self.shares.read().unwrap().get(id).cloned()
+    }
+
+    pub fn revoke(&self, id: &str) -> std::io::Result<Option<Share>> {
+        let mut shares = self.shares.write().unwrap();
+        let share = shares.remove(id);
+        self.save(&shares)?;
+        Ok(share)
+    }
+
+    // Cookie value proving the password of a share was entered
+    // Changing the password invalidates it
+    fn unlock_mac(&self, share: &Share) -> HmacSha256 {
+        self.mac(&format!(
+            "unlock:{}:{}",
+            share.id,
+            share.password.as_deref().unwrap_or("")
+        ))
+    }
+
+    pub fn unlock_key(&self, share: &Share) -> String {
+        base64::encode_config(
+            self.unlock_mac(share).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    pub fn unlocked(&self, share: &Share, key: &str) -> bool {
+        match base64::decode_config(key, base64::URL_SAFE_NO_PAD) {
+            Ok(key) => self.unlock_mac(share).verify_slice(&key).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn info(&self, share: Share) -> ShareInfo {
+        ShareInfo {
+            token: self.token(&share),
+            protected: share.password.is_some(),
+            id: share.id,
+            path: share.path,
+            created: share.created,
+            expires: share.expires,
+            download: share.download,
+            owner: share.owner,
+        }
+    }
+}
+
+// Rewrite paths in a listing from root-relative to "_s/<token>/..."
+// so the frontend requests them through the share
+fn rebase(entry: &mut FileEntry, share: &Share, token: &str) {
+    let prefix = Path::new("_s").join(token);
+    if let Ok(path) = entry.path.strip_prefix(&share.path) {
+        entry.path = prefix.join(path);
+    }
+    entry.thumb = entry
+        .thumb
+        .as_ref()
+        .and_then(|t| t.strip_prefix(&share.path).ok())
+        .map(|t| prefix.join(t));
+}
+
+fn rebase_dir(dir: &mut Dir, share: &Share, token: &str) {
+    for entry in dir.folders.iter_mut().chain(dir.files.iter_mut()) {
+        rebase(entry, share, token);
+    }
+    dir.title = Path::new(&dir.title)
+        .strip_prefix(&share.path)
+        .map(|t| t.display().to_string())
+        .unwrap_or_default();
+    dir.base_path = format!("/_s/{}/", token);
+    dir.read_only = true;
+    dir.shared = true;
+    dir.download = share.download;
+}
+
+// Share for a request, checking the password cookie of protected shares
+fn open_share(state: &AppState, req: &HttpRequest, token: &str) -> Result<Share, Error> {
+    let share = state
+        .shares
+        .verify(token)
+        .ok_or_else(|| ErrorNotFound("Share not found"))?;
+
+    if share.password.is_some() {
+        let unlocked = req
+            .cookie(&format!("{}{}", UNLOCK_COOKIE, share.id))
+            .map(|c| state.shares.unlocked(&share, c.value()))
+            .unwrap_or(false);
+        if !unlocked {
+            return Err(ErrorUnauthorized("Password required"));
+        }
+    }
+
+    Ok(share)
+}
+
+// Resolve a path inside a shared folder
+fn share_path<'a>(
+    state: &'a AppState,
+    share: &Share,
+    file: &str,
+) -> Result<(&'a Library, PathBuf), Error> {
+    Ok(state.resolve(&format!("{}/{}", share.path.display(), file))?)
+}
+
+// Without downloads only media can be opened (to be viewed in the browser)
+fn viewable(path: &Path) -> bool {
+    let mime = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(file_extension_to_mime);
+    matches!(mime, Some(mime) if mime.type_() == "image" || mime.type_() == "video")
+}
+
+#[get("/_s/{token}/{file:.*}")]
+pub async fn route(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Path((token, file)): web::Path<(String, String)>,
+) -> Result<Either<Either<NamedFile, HttpResponse>, Json<FileStat>>, Error> {
+    let share = open_share(&state, &req, &token)?;
+    let (library, path) = share_path(&state, &share, &file)?;
+    let media = viewable(&path);
+
+    let as_folder = file.ends_with('/');
+    if wants_json(&req) && !is_folder(&path, as_folder) {
+        let mut stat = stat_path(library, path).await?;
+        rebase(&mut stat.entry, &share, &token);
+        return Ok(Either::B(Json(stat)));
+    }
+
+    match open_path(library, path.clone(), as_folder).await? {
+        Either::A(_) if !share.download && !media => {
+            Err(ErrorForbidden("Downloads are disabled for this share"))
+        }
+        Either::A(file) => Ok(Either::A(with_digest(&state, &req, path, file).await?)),
+        Either::B(mut dir) => {
+            rebase_dir(&mut dir, &share, &token);
+            Ok(Either::A(Either::B(dir_response(&req, &dir)?)))
+        }
+    }
+}
+
+// Folder listing inside a share, see crate::list
+#[get("/_api/list/_s/{token}/{file:.*}")]
+pub async fn list_dir(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Path((token, file)): web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let share = open_share(&state, &req, &token)?;
+    let (library, path) = share_path(&state, &share, &file)?;
+
+    match open_path(library, path, true).await? {
+        Either::A(_) => Err(ErrorBadRequest("Not a folder")),
+        Either::B(mut dir) => {
+            rebase_dir(&mut dir, &share, &token);
+            dir_response(&req, &dir)
+        }
+    }
+}
+
+// Details of a file inside a share, see crate::file_stat
+#[get("/_api/stat/_s/{token}/{file:.*}")]
+pub async fn file_stat(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Path((token, file)): web::Path<(String, String)>,
+) -> Result<Json<FileStat>, Error> {
+    let share = open_share(&state, &req, &token)?;
+    let (library, path) = share_path(&state, &share, &file)?;
+
+    let mut stat = stat_path(library, path).await?;
+    rebase(&mut stat.entry, &share, &token);
+    Ok(Json(stat))
+}
+
+// Comic reader pages for an archive inside a share
+#[get("/_api/pages/_s/{token}/{file:.*}")]
+pub async fn pages(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    web::Path((token, file)): web::Path<(String, String)>,
+) -> Result<Json<Vec<FileEntry>>, Error> {
+    let share = open_share(&state, &req, &token)?;
+    let (library, path) = share_path(&state, &share, &file)?;
+
+    let root = library.root.to_owned();
+    let prefix = PathBuf::from(&library.name);
+    let mut pages = web::block(move || archive::get_pages(&root, &path))
+        .await
+        .map_err(DirError::from)?;
+    for page in pages.iter_mut() {
+        page.prefix(&prefix);
+        rebase(page, &share, &token);
+    }
+    Ok(Json(pages))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnlockRequest {
+    password: String,
+}
+
+#[post("/_api/unlock/{token}")]
+pub async fn unlock(
+    state: web::Data<AppState>,
+    web::Path(token): web::Path<String>,
+    req: web::Json<UnlockRequest>,
+) -> Result<HttpResponse, Error> {
+    let share = state
+        .shares
+        .verify(&token)
+        .ok_or_else(|| ErrorNotFound("Share not found"))?;
+    let hash = match &share.password {
+        Some(hash) => hash.to_owned(),
+        None => return Ok(HttpResponse::NoContent().finish()),
+    };
+
+    let password = req.into_inner().password;
+    let valid =
+        web::block(move || -> Result<bool, ()> { Ok(auth::verify_password(&password, &hash)) })
+            .await?;
+    if !valid {
+        return Err(ErrorUnauthorized("Invalid password"));
+    }
+
+    let cookie = Cookie::build(
+        format!("{}{}", UNLOCK_COOKIE, share.id),
+        state.shares.unlock_key(&share),
+    )
+    .path("/")
+    .http_only(true)
+    .same_site(SameSite::Strict)
+    .finish();
+    Ok(HttpResponse::NoContent().cookie(cookie).finish())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateRequest {
+    path: String,
+    // Seconds until the link stops working, never when missing
+    expires_in: Option<u64>,
+    password: Option<String>,
+    #[serde(default = "default_download")]
+    download: bool,
+}
+
+fn default_download() -> bool {
+    true
+}
+
+// Creating a share exposes a folder publicly, so it requires admin access to it
+#[post("/_api/shares")]
+pub async fn create(
+    state: web::Data<AppState>,
+    req: web::Json<CreateRequest>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let CreateRequest {
+        path,
+        expires_in,
+        password,
+        download,
+    } = req.into_inner();
+
+    let (library, full) = state
+        .resolve(&path)
+        .map_err(|_| ErrorBadRequest("Invalid path"))?;
+    if !full.is_dir() {
+        return Err(ErrorBadRequest("Only folders can be shared"));
+    }
+    acl::require(&state, identity.as_ref(), library, &full, Access::Admin)?;
+
+    let password = match password.filter(|p| !p.is_empty()) {
+        Some(password) => Some(
+            web::block(move || auth::hash_password(&password).map_err(|e| e.to_string()))
+                .await
+                .map_err(ErrorInternalServerError)?,
+        ),
+        None => None,
+    };
+
+    let created = now();
+    let share = Share {
+        id: String::new(),
+        path: encode_path(&library.client_path(&full).expect("Resolved path"))?,
+        created,
+        expires: expires_in
+            .map(|e| {
+                created
+                    .checked_add(e)
+                    .ok_or(ErrorBadRequest("Invalid expiry"))
+            })
+            .transpose()?,
+        password,
+        download,
+        owner: identity.map(|i| i.name),
+    };
+    let share = state
+        .shares
+        .create(share)
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(state.shares.info(share)))
+}
+
+// Shares created by the user, or all of them without authentication
+#[get("/_api/shares")]
+pub async fn list(
+    state: web::Data<AppState>,
+    identity: Option<web::ReqData<Identity>>,
+) -> HttpResponse {
+    let owner = identity.as_ref().map(|i| i.name.as_str());
+    let shares: Vec<ShareInfo> = state
+        .shares
+        .list(owner)
+        .into_iter()
+        .map(|s| state.shares.info(s))
+        .collect();
+    HttpResponse::Ok().json(shares)
+}
+
+#[delete("/_api/shares/{id}")]
+pub async fn revoke(
+    state: web::Data<AppState>,
+    web::Path(id): web::Path<String>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let share = state
+        .shares
+        .get(&id)
+        .ok_or_else(|| ErrorNotFound("Share not found"))?;
+
+    // Owners can revoke their links, as can admins of the shared folder
+    let identity = identity.map(|i| i.into_inner());
+    let owner = identity.as_ref().map(|i| &i.name) == share.owner.as_ref();
+    if !owner {
+        let (library, path) = share_path(&state, &share, "")?;
+        acl::require(&state, identity.as_ref(), library, &path, Access::Admin)?;
+    }
+
+    state.shares.revoke(&id).map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn share(expires: Option<u64>) -> Share {
+        Share {
+            id: String::new(),
+            path: PathBuf::from("imgs"),
+            created: now(),
+            expires,
+            password: None,
+            download: true,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        let shares = Shares::load(None).unwrap();
+        let share = shares.create(share(None)).unwrap();
+        let token = shares.token(&share);
+
+        assert_eq!(shares.verify(&token), Some(share.to_owned()));
+        assert!(shares.verify(&share.id).is_none());
+        assert!(shares.verify(&format!("{}.invalid", share.id)).is_none());
+
+        // Tokens signed with another secret are rejected
+        let other = Shares::load(None).unwrap();
+        assert!(other.verify(&other.token(&share)).is_none());
+        assert!(shares.verify(&other.token(&share)).is_none());
+
+        shares.revoke(&share.id).unwrap();
+        assert!(shares.verify(&token).is_none());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let shares = Shares::load(None).unwrap();
+        let expired = shares.create(share(Some(now() - 1))).unwrap();
+        let valid = shares.create(share(Some(now() + 60))).unwrap();
+
+        assert!(shares.verify(&shares.token(&expired)).is_none());
+        assert!(shares.verify(&shares.token(&valid)).is_some());
+        assert_eq!(shares.list(None), vec![valid]);
+    }
+
+    #[test]
+    fn test_unlock() {
+        let shares = Shares::load(None).unwrap();
+        let mut protected = share(None);
+        protected.password = Some(auth::hash_password("secret").unwrap());
+        let protected = shares.create(protected).unwrap();
+
+        let key = shares.unlock_key(&protected);
+        assert!(shares.unlocked(&protected, &key));
+        assert!(!shares.unlocked(&protected, "invalid"));
+
+        let other = shares.create(share(None)).unwrap();
+        assert!(!shares.unlocked(&other, &key));
+    }
+
+    #[test]
+    fn test_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("shares.json");
+        let path = file.to_str().unwrap();
+
+        let shares = Shares::load(Some(path)).unwrap();
+        let share = shares.create(share(None)).unwrap();
+        let token = shares.token(&share);
+
+        // Links keep working after a restart
+        let reloaded = Shares::load(Some(path)).unwrap();
+        assert_eq!(reloaded.verify(&token), Some(share.to_owned()));
+
+        // Concurrent changes all make it to the file
+        let shares = std::sync::Arc::new(reloaded);
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let shares = shares.clone();
+                std::thread::spawn(move || {
+                    let created = shares.create(self::share(None)).unwrap();
+                    shares.revoke(&created.id).unwrap();
+                    shares.create(self::share(None)).unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let reloaded = Shares::load(Some(path)).unwrap();
+        assert_eq!(reloaded.list(None).len(), 9);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_persist_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("shares.json");
+        let mode = || std::fs::metadata(&file).unwrap().permissions().mode() & 0o777;
+
+        Shares::load(Some(file.to_str().unwrap())).unwrap();
+        assert_eq!(mode(), 0o600);
+
+        // Files from before are made private too
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        Shares::load(Some(file.to_str().unwrap())).unwrap();
+        assert_eq!(mode(), 0o600);
+    }
+
+    #[test]
+    fn test_rebase() {
+        let shared = share(None);
+        let mut entry = FileEntry::new(
+            String::from("flower.jpg"),
+            PathBuf::from("imgs/flower.jpg"),
+            0,
+            0,
+        );
+        entry.thumb = Some(PathBuf::from("imgs/.th/flower.jpg.avif"));
+
+        rebase(&mut entry, &shared, "abc.def");
+        assert_eq!(entry.path, PathBuf::from("_s/abc.def/flower.jpg"));
+        assert_eq!(
+            entry.thumb,
+            Some(PathBuf::from("_s/abc.def/.th/flower.jpg.avif"))
+        );
+    }
+}
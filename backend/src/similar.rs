@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use actix_files::file_extension_to_mime;
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    get, web, Error, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::acl;
+use crate::auth::Identity;
+use crate::check_read;
+use crate::config::Hidden;
+use crate::dir::{encode_name, FileEntry};
+use crate::jobs::{Job, Jobs, Progress};
+use crate::state::AppState;
+
+// Finished indexes are answered from memory for this long
+pub(crate) const SIMILAR_TTL: Duration = Duration::from_secs(10 * 60);
+// Hashes of the images of a folder, in its thumbnails folder
+const HASHES: &str = "dhash.txt";
+// Differing bits (out of 64) up to which images are alike
+const MAX_DISTANCE: u32 = 10;
+const LIMIT: usize = 50;
+
+#[derive(Deserialize, Debug)]
+pub struct SimilarQuery {
+    distance: Option<u32>,
+    limit: Option<usize>,
+    // Index again, eg after adding photos
+    #[serde(default)]
+    refresh: bool,
+}
+
+// Perceptual hashes of the images of a library
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    hashes: Arc<Vec<(PathBuf, u64)>>,
+}
+
+// Indexes by user (see acl::scan_owner) and library root
+pub type SimilarJobs = Jobs<(Option<String>, PathBuf), Index>;
+
+// An image alike the one asked about
+#[derive(Serialize, Debug)]
+pub struct Similar {
+    // Differing bits of the hashes, 0 for the same picture
+    distance: u32,
+    file: FileEntry,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SimilarImages {
+    // dHash of the image, hex encoded
+    hash: String,
+    similar: Vec<Similar>,
+}
+
+// Whether `path` is an image, by its extension
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| file_extension_to_mime(&ext.to_string_lossy()).type_() == "image")
+}
+
+// Difference hash of a 9x8 grayscale image, one bit per pair of neighbours in a row:
+// set when the left pixel is darker
+pub fn dhash(pixels: &[u8]) -> u64 {
+    let mut hash = 0;
+    for row in pixels.chunks_exact(9).take(8) {
+        for pair in row.windows(2) {
+            hash = hash << 1 | (pair[0] < pair[1]) as u64;
+        }
+    }
+    hash
+}
+
+// Difference hash of an image file, scaled down by ImageMagick
+pub fn dhash_file(path: &Path) -> io::Result<u64> {
+    let mut frame = path.as_os_str().to_owned();
+    frame.push("[0]");
+    let output = Command::new("convert")
+        .args(["-define", "jpeg:size=64x64"])
+        .arg(frame)
+        .args([
+            "-colorspace",
+            "Gray",
+            "-resize",
+            "9x8!",
+            "-depth",
+            "8",
+            "gray:-",
+        ])
+        .output()?;
+    if !output.status.success() || output.stdout.len() != 72 {
+        return Err(io::Error::other("convert failed"));
+    }
+    Ok(dhash(&output.stdout))
+}
+
+// What to hash for an image: its thumbnail when one was generated, a lot quicker to
+// decode than the original, otherwise the image itself
+pub fn hash_source(path: &Path, format: Option<&str>) -> PathBuf {
+    format
+        .and_then(|format| FileEntry::file_path_to_thumb(path, format).ok())
+        .filter(|thumb| thumb.is_file())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+// Size, modification time (in seconds) and hash by file name
+type Hashes = HashMap<String, (u64, u64, u64)>;
+
+fn read_hashes(th: &Path) -> Hashes {
+    let content = fs::read_to_string(th.join(HASHES)).unwrap_or_default();
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let size = fields.next()?.parse().ok()?;
+            let modified = fields.next()?.parse().ok()?;
+            Some((fields.next()?.to_owned(), (size, modified, hash)))
+        })
+        .collect()
+}
+
+fn write_hashes(th: &Path, hashes: &Hashes) -> io::Result<()> {
+    let mut names: Vec<&String> = hashes.keys().collect();
+    names.sort();
+    let content: String = names
+        .into_iter()
+        .map(|name| {
+            let (size, modified, hash) = hashes[name];
+            format!("{:016x}\t{}\t{}\t{}\n", hash, size, modified, name)
+        })
+        .collect();
+    // Indexes of several users may write the same folder at once (see SimilarJobs),
+    // each replaces the file whole
+    let mut tmp = tempfile::Builder::new().prefix(".dhash").tempfile_in(th)?;
+    tmp.write_all(content.as_bytes())?;
+    tmp.persist(th.join(HASHES))?;
+    Ok(())
+}
+
+// How a library is indexed
+pub struct Scan<'a, F> {
+    // Dot files are left out otherwise
+    pub show_hidden: bool,
+    // Hashes are kept next to the thumbnails of each folder, unless the library is
+    // read-only; folders without thumbnails don't get a thumbnails folder for them
+    pub read_only: bool,
+    // Whether the user can see a path (and whether it is a folder), see acl::scan_filter
+    pub visible: &'a dyn Fn(&Path, bool) -> bool,
+    // Computes the hash of new or changed images
+    pub hash: &'a F,
+}
+
+// Hashes of the images under `folder`, each image counts towards the progress of `job`
+pub fn index<F>(folder: &Path, scan: &Scan<F>, job: &Job<Index>) -> Vec<(PathBuf, u64)>
+where
+    F: Fn(&Path) -> io::Result<u64>,
+{
+    let mut found = vec![];
+    index_folder(folder, scan, job, &mut found);
+    found
+}
+
+fn index_folder<F>(folder: &Path, scan: &Scan<F>, job: &Job<Index>, found: &mut Vec<(PathBuf, u64)>)
+where
+    F: Fn(&Path) -> io::Result<u64>,
+{
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let th = folder.join(".th");
+    let known = read_hashes(&th);
+    let mut hashes = Hashes::new();
+    let mut changed = false;
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let lossy = file_name.to_string_lossy();
+        if lossy == ".th" || (!scan.show_hidden && lossy.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if meta.is_dir() {
+            if (scan.visible)(&path, true) {
+                index_folder(&path, scan, job, found);
+            }
+            continue;
+        }
+        let name = match encode_name(&file_name) {
+            Ok(name) if !name.contains('\n') => name,
+            _ => continue,
+        };
+        if !meta.is_file() || !is_image(&path) {
+            continue;
+        }
+        if !(scan.visible)(&path, false) {
+            // Kept for the users who can see it
+            if let Some(&known) = known.get(&name) {
+                hashes.insert(name, known);
+            }
+            continue;
+        }
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let value = match known.get(&name) {
+            Some(&(size, at, value)) if size == meta.len() && at == modified => value,
+            _ => match (scan.hash)(&path) {
+                Ok(value) => {
+                    changed = true;
+                    value
+                }
+                Err(_) => continue,
+            },
+        };
+        job.add(meta.len());
+        hashes.insert(name, (meta.len(), modified, value));
+        found.push((path, value));
+    }
+    // Also drops the hashes of images deleted since
+    if !scan.read_only && th.is_dir() && (changed || hashes.len() != known.len()) {
+        // Hashes that can't be saved (eg read-only disks) are computed again next time
+        let _ = write_hashes(&th, &hashes);
+    }
+}
+
+// Images of the library that look like the requested one, closest first, indexed
+// in the background: answers 202 with the progress until the index is done
+#[get("/_api/similar/{file:.*}")]
+pub async fn similar(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<SimilarQuery>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let (library, path) = state.resolve(req.match_info().query("file"))?;
+    check_read(&state, identity.as_ref(), library, &path)?;
+    if !path.is_file() || !is_image(&path) {
+        return Err(ErrorBadRequest("Not an image"));
+    }
+
+    let root = library.root.clone();
+    let show_hidden = library.hidden == Hidden::Show;
+    let read_only = library.read_only;
+    let format = library.thumb_format().map(str::to_owned);
+    let owner = acl::scan_owner(&state, identity.as_ref());
+    let visible = acl::scan_filter(&state, identity.as_ref(), library);
+    let key = (owner, root.clone());
+    let thumbs = format.clone();
+    let job = state.similar.start(key, query.refresh, move |job| {
+        let hash = |p: &Path| dhash_file(&hash_source(p, thumbs.as_deref()));
+        let scan = Scan {
+            show_hidden,
+            read_only,
+            visible: &visible,
+            hash: &hash,
+        };
+        Index {
+            hashes: Arc::new(index(&root, &scan, job)),
+        }
+    });
+
+    if job.failed() {
+        return Err(ErrorInternalServerError("Index failed"));
+    }
+    let progress = job.progress();
+    let hashes = match progress.result {
+        Some(index) => index.hashes,
+        None => {
+            let progress: Progress<SimilarImages> = Progress {
+                done: false,
+                files: progress.files,
+                bytes: progress.bytes,
+                result: None,
+            };
+            return Ok(HttpResponse::Accepted().json(progress));
+        }
+    };
+
+    // Images added since the index was made are hashed now
+    let hash = match hashes.iter().find(|(p, _)| p == &path) {
+        Some(&(_, hash)) => hash,
+        None => {
+            let image = hash_source(&path, format.as_deref());
+            web::block(move || dhash_file(&image))
+                .await
+                .map_err(|_| ErrorInternalServerError("Could not hash image"))?
+        }
+    };
+
+    let distance = query.distance.unwrap_or(MAX_DISTANCE).min(32);
+    let limit = query.limit.unwrap_or(LIMIT).clamp(1, 500);
+    let mut close: Vec<(u32, &PathBuf)> = hashes
+        .iter()
+        .filter(|(p, _)| p != &path)
+        .map(|(p, h)| ((h ^ hash).count_ones(), p))
+        .filter(|(d, _)| *d <= distance)
+        .collect();
+    close.sort();
+
+    let thumbs = library.thumbs();
+    let similar = close
+        .into_iter()
+        .filter_map(|(distance, p)| {
+            // Images deleted since the index was made are left out
+            let mut file = FileEntry::stat(&library.root, p, thumbs).ok()?;
+            if !library.name.is_empty() {
+                file.prefix(Path::new(&library.name));
+            }
+            Some(Similar { distance, file })
+        })
+        .take(limit)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(Progress {
+        done: true,
+        files: progress.files,
+        bytes: progress.bytes,
+        result: Some(SimilarImages {
+            hash: format!("{:016x}", hash),
+            similar,
+        }),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_dhash() {
+        // Brighter to the right: every left neighbour is darker
+        let gradient: Vec<u8> = (0..72).map(|i| (i % 9) as u8 * 20).collect();
+        assert_eq!(dhash(&gradient), u64::MAX);
+        let flat = [128; 72];
+        assert_eq!(dhash(&flat), 0);
+        // A single brighter pixel is darker than none of its right neighbours
+        let mut spot = flat;
+        spot[10] = 200;
+        assert_eq!(dhash(&spot).count_ones(), 1);
+    }
+
+    #[test]
+    fn test_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("a/.th")).unwrap();
+        fs::write(root.join("a/photo.jpg"), "one").unwrap();
+        fs::write(root.join("a/b/photo.png"), "two").unwrap();
+        fs::write(root.join("a/.hidden.jpg"), "three").unwrap();
+        fs::write(root.join("a/note.txt"), "four").unwrap();
+
+        let calls = Cell::new(0);
+        let hash = |p: &Path| {
+            calls.set(calls.get() + 1);
+            Ok(fs::metadata(p)?.len())
+        };
+        let scan = Scan {
+            show_hidden: false,
+            read_only: false,
+            visible: &|_, _| true,
+            hash: &hash,
+        };
+        let job = Job::default();
+        let mut found = index(root, &scan, &job);
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                (root.join("a/b/photo.png"), 3),
+                (root.join("a/photo.jpg"), 3)
+            ]
+        );
+        assert_eq!(calls.get(), 2);
+        assert!(root.join("a/.th").join(HASHES).exists());
+        // Folders without thumbnails don't get a thumbnails folder
+        assert!(!root.join("a/b/.th").exists());
+
+        // Known images aren't hashed again, changed ones and those without a
+        // thumbnails folder to keep their hash are
+        fs::write(root.join("a/photo.jpg"), "changed").unwrap();
+        let job = Job::default();
+        let found = index(root, &scan, &job);
+        assert_eq!(calls.get(), 4);
+        assert!(found.contains(&(root.join("a/photo.jpg"), 7)));
+        assert_eq!(job.progress().files, 2);
+
+        // Nothing is written in read-only libraries
+        fs::write(root.join("a/photo.jpg"), "changed again").unwrap();
+        let read_only = Scan {
+            read_only: true,
+            ..scan
+        };
+        index(root, &read_only, &Job::default());
+        assert_eq!(read_hashes(&root.join("a/.th"))["photo.jpg"].0, 7);
+
+        // Images the user can't see are neither hashed nor counted, and their
+        // hashes are kept for those who can
+        let calls_before = calls.get();
+        let hidden = Scan {
+            visible: &|p, _| !p.ends_with("photo.jpg"),
+            ..scan
+        };
+        let job = Job::default();
+        assert_eq!(index(root, &hidden, &job).len(), 1);
+        // Only b/photo.png, which has no thumbnails folder
+        assert_eq!(calls.get(), calls_before + 1);
+        assert_eq!(job.progress().files, 1);
+        assert!(read_hashes(&root.join("a/.th")).contains_key("photo.jpg"));
+
+        fs::remove_file(root.join("a/photo.jpg")).unwrap();
+        index(root, &scan, &Job::default());
+        assert!(read_hashes(&root.join("a/.th")).is_empty());
+        // Written whole, without leaving temporary files behind
+        let names: Vec<_> = fs::read_dir(root.join("a/.th"))
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name())
+            .collect();
+        assert_eq!(names, vec![HASHES]);
+        let with_hidden = Scan {
+            show_hidden: true,
+            ..scan
+        };
+        assert_eq!(index(root, &with_hidden, &Job::default()).len(), 2);
+    }
+
+    #[test]
+    fn test_hash_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("photo.jpg");
+        fs::write(&image, "image").unwrap();
+        assert_eq!(hash_source(&image, Some("avif")), image);
+        fs::create_dir_all(dir.path().join(".th")).unwrap();
+        let thumb = dir.path().join(".th/photo.jpg.avif");
+        fs::write(&thumb, "thumb").unwrap();
+        assert_eq!(hash_source(&image, Some("avif")), thumb);
+        assert_eq!(hash_source(&image, None), image);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::acl::Acl;
+use crate::auth::{Identity, Sessions, Users};
+use crate::cache::ListingCache;
+use crate::checksum::{ChecksumCache, CHECKSUMS};
+use crate::config::{Config, Cover, Hidden};
+use crate::cover::Mosaics;
+use crate::dir::{resolve_path, DirError, Thumbs};
+use crate::dupes::{DupesJobs, DUPES_TTL};
+use crate::jobs::Jobs;
+use crate::share::Shares;
+use crate::similar::{SimilarJobs, SIMILAR_TTL};
+use crate::stats::FolderStatsCache;
+use crate::usage::{UsageJobs, USAGE_TTL};
+
+// A root dir files are served from
+// Named libraries are served under "/<name>/", the single unnamed one (--dir) at "/"
+#[derive(Debug)]
+pub struct Library {
+    pub name: String,
+    pub root: PathBuf,
+    pub read_only: bool,
+    pub format: String,
+    pub no_thumbs: bool,
+    pub cover: Cover,
+    pub hidden: Hidden,
+    pub listings: Arc<ListingCache>,
+    pub folder_stats: FolderStatsCache,
+    pub mosaics: Mosaics,
+}
+
+impl Library {
+    // Thumbnail format, unless thumbnails are disabled
+    pub fn thumb_format(&self) -> Option<&str> {
+        if self.no_thumbs {
+            None
+        } else {
+            Some(&self.format)
+        }
+    }
+
+    // How thumbnails are looked up in listings, unless thumbnails are disabled
+    pub fn thumbs(&self) -> Option<Thumbs<'_>> {
+        self.thumb_format().map(|format| Thumbs {
+            format,
+            cover: self.cover,
+            read_only: self.read_only,
+            mosaics: Some(&self.mosaics),
+        })
+    }
+
+    // Whether a file name is left out of listings and can not be requested
+    pub fn hides(&self, name: &str) -> bool {
+        self.hidden == Hidden::Hide && name.starts_with('.') && name != ".th"
+    }
+
+    // Path as used in URLs, listings, the ACL and shares
+    fn virtual_path(&self, relative: &Path) -> PathBuf {
+        if relative.as_os_str().is_empty() {
+            PathBuf::from(&self.name)
+        } else {
+            Path::new(&self.name).join(relative)
+        }
+    }
+
+    // A path resolved in this library (see AppState::resolve) as seen by clients
+    pub fn client_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.virtual_path(path.strip_prefix(&self.root).ok()?))
+    }
+}
+
+// Everything an instance serves from, passed to handlers through web::Data
+pub struct AppState {
+    pub config: Config,
+    pub users: Option<Users>,
+    pub sessions: Sessions,
+    pub acl: Option<Acl>,
+    pub shares: Shares,
+    pub libraries: Vec<Library>,
+    // Outcome of checking Basic credentials (by hash of the header), so the password
+    // hash is not recomputed on every request, nor for repeated wrong ones
+    pub(crate) basic_cache: RwLock<HashMap<String, Option<Identity>>>,
+    // Disk usage scans (see usage.rs)
+    pub(crate) usage: UsageJobs,
+    // Duplicate file scans (see dupes.rs)
+    pub(crate) dupes: DupesJobs,
+    // Perceptual hashes of library images (see similar.rs)
+    pub(crate) similar: SimilarJobs,
+    // Digests of recently hashed files (see checksum.rs)
+    pub(crate) checksums: ChecksumCache,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<Self, String> {
+        let users = match &config.users {
+            Some(path) => {
+                Some(Users::load(path).map_err(|e| format!("Could not load users file: {}", e))?)
+            }
+            None => None,
+        };
+        let acl = match &config.acl {
+            Some(path) => {
+                Some(Acl::load(path).map_err(|e| format!("Could not load ACL file: {}", e))?)
+            }
+            None => None,
+        };
+        // Cached listings would keep the cover picked at random for each listing
+        let listing_cache = match config.cover {
+            Cover::Random => 0,
+            _ => config.listing_cache,
+        };
+        // Where the ACL changes who can read, in any library: mosaics are shared by
+        // every library a folder is in (see cover.rs)
+        let roots: Vec<(&str, &Path)> = if config.libraries.is_empty() {
+            vec![("", Path::new(&config.dir))]
+        } else {
+            config
+                .libraries
+                .iter()
+                .map(|spec| (spec.name.as_str(), spec.dir.as_path()))
+                .collect()
+        };
+        let restricted = Arc::new(
+            acl.as_ref()
+                .map(|acl| acl.rule_paths(&roots))
+                .unwrap_or_default(),
+        );
+        let libraries = if config.libraries.is_empty() {
+            let listings = Arc::new(ListingCache::new(listing_cache));
+            vec![Library {
+                name: String::new(),
+                root: PathBuf::from(&config.dir),
+                read_only: config.read_only,
+                format: config.format.clone(),
+                no_thumbs: config.no_thumbs,
+                cover: config.cover,
+                hidden: config.hidden,
+                mosaics: Mosaics::new(listings.clone(), restricted.clone()),
+                listings,
+                folder_stats: FolderStatsCache::new(
+                    config.hidden == Hidden::Show,
+                    restricted.clone(),
+                ),
+            }]
+        } else {
+            config
+                .libraries
+                .iter()
+                .map(|spec| {
+                    let listings = Arc::new(ListingCache::new(listing_cache));
+                    Library {
+                        name: spec.name.clone(),
+                        root: spec.dir.clone(),
+                        read_only: spec.read_only.unwrap_or(config.read_only),
+                        format: spec.format.clone().unwrap_or_else(|| config.format.clone()),
+                        no_thumbs: config.no_thumbs,
+                        cover: config.cover,
+                        hidden: spec.hidden.unwrap_or(config.hidden),
+                        mosaics: Mosaics::new(listings.clone(), restricted.clone()),
+                        listings,
+                        folder_stats: FolderStatsCache::new(
+                            spec.hidden.unwrap_or(config.hidden) == Hidden::Show,
+                            restricted.clone(),
+                        ),
+                    }
+                })
+                .collect()
+        };
+        let shares = Shares::load(config.shares.as_deref())
+            .map_err(|e| format!("Could not load shares file: {}", e))?;
+
+        Ok(Self {
+            config,
+            users,
+            sessions: Sessions::default(),
+            acl,
+            shares,
+            libraries,
+            basic_cache: RwLock::new(HashMap::new()),
+            usage: Jobs::new(USAGE_TTL),
+            dupes: Jobs::new(DUPES_TTL),
+            similar: Jobs::new(SIMILAR_TTL),
+            checksums: ChecksumCache::new(CHECKSUMS),
+        })
+    }
+
+    // Whether the root lists named libraries
+    pub fn named(&self) -> bool {
+        self.libraries.iter().any(|l| !l.name.is_empty())
+    }
+
+    pub fn library(&self, name: &str) -> Option<&Library> {
+        self.libraries.iter().find(|l| l.name == name)
+    }
+
+    // Resolve a request path (eg, "/photos/imgs/flower.jpg") to its library and a path inside it
+    pub fn resolve(&self, file: &str) -> Result<(&Library, PathBuf), DirError> {
+        let (library, rest) = if self.named() {
+            let file = file.trim_start_matches('/');
+            let (name, rest) = file.split_once('/').unwrap_or((file, ""));
+            (self.library(name).ok_or(DirError::NotFound)?, rest)
+        } else {
+            (&self.libraries[0], file)
+        };
+
+        let path = resolve_path(&library.root, rest)?;
+        let hidden = path
+            .strip_prefix(&library.root)
+            .expect("Valid path")
+            .components()
+            .any(|c| matches!(c, Component::Normal(n) if library.hides(&n.to_string_lossy())));
+        if hidden {
+            return Err(DirError::NotFound);
+        }
+
+        Ok((library, path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_resolve() {
+        let config = Config::from_iter(&[
+            "hview",
+            "--library",
+            "photos=./test-fixtures/imgs",
+            "--library",
+            "all=./test-fixtures;hidden=hide",
+        ]);
+        let state = AppState::new(config).unwrap();
+
+        let (library, path) = state.resolve("/photos/flower.jpg").unwrap();
+        assert_eq!(library.name, "photos");
+        assert_eq!(path, Path::new("./test-fixtures/imgs/flower.jpg"));
+        assert!(state.resolve("/docs/").is_err());
+        assert!(state.resolve("/all/.hidden").is_err());
+        assert!(state.resolve("/all/.th/x.avif").is_ok());
+
+        // Paths are seen through the library they were requested in
+        assert_eq!(
+            library.client_path(&path).unwrap(),
+            Path::new("photos/flower.jpg")
+        );
+        let (library, path) = state.resolve("/all/imgs/flower.jpg").unwrap();
+        assert_eq!(library.name, "all");
+        assert_eq!(
+            library.client_path(&path).unwrap(),
+            Path::new("all/imgs/flower.jpg")
+        );
+        let (library, path) = state.resolve("/all/").unwrap();
+        assert_eq!(library.client_path(&path).unwrap(), Path::new("all"));
+        assert!(library.client_path(Path::new("/elsewhere")).is_none());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::dir::{resolve_path, Dir};
+
+// Counted totals are used for this long before they are counted again
+const STATS_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Totals of everything inside a folder, sub folders included (thumbnails are left out)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct FolderStats {
+    pub files: u64,
+    pub bytes: u64,
+    // Modification time of the newest file, 0 when there are none
+    pub newest: u64,
+    pub newest_string: String,
+}
+
+// Folder totals of a library, counted by a background thread so listings never wait on them
+// Listings get the totals known so far; missing and outdated ones are queued
+#[derive(Debug)]
+pub struct FolderStatsCache {
+    show_hidden: bool,
+    // Paths of ACL rules (see Acl::rule_paths): folders with one inside them get no totals
+    restricted: Arc<Vec<PathBuf>>,
+    counted: Arc<Mutex<HashMap<PathBuf, Counted>>>,
+    // Started with the first folder to count
+    queue: Mutex<Option<Sender<PathBuf>>>,
+}
+
+#[derive(Debug)]
+struct Counted {
+    stats: Option<FolderStats>,
+    at: Instant,
+    stale: bool,
+    queued: bool,
+}
+
+impl FolderStatsCache {
+    // Dot files are left out of the totals unless `show_hidden`
+    pub fn new(show_hidden: bool, restricted: Arc<Vec<PathBuf>>) -> Self {
+        Self {
+            show_hidden,
+            restricted,
+            counted: Arc::new(Mutex::new(HashMap::new())),
+            queue: Mutex::new(None),
+        }
+    }
+
+    // Totals of `folder` if already counted, (re)counting them in the background when needed
+    // Folders with ACL rules inside them have none
+    pub fn get(&self, folder: &Path) -> Option<FolderStats> {
+        if self
+            .restricted
+            .iter()
+            .any(|r| r.starts_with(folder) && r != folder)
+        {
+            return None;
+        }
+        let mut counted = self.counted.lock().unwrap();
+        let entry = counted.entry(folder.to_path_buf()).or_insert(Counted {
+            stats: None,
+            at: Instant::now(),
+            stale: true,
+            queued: false,
+        });
+        if !entry.queued && (entry.stale || entry.at.elapsed() > STATS_TTL) {
+            entry.queued = true;
+            self.enqueue(folder.to_path_buf());
+        }
+        entry.stats.clone()
+    }
+
+    // Set the totals of the sub folders in a listing, whose paths are inside `root`
+    pub fn fill(&self, root: &Path, dir: &mut Dir) {
+        for entry in dir.folders.iter_mut() {
+            if let Ok(path) = resolve_path(root, &entry.path.to_string_lossy()) {
+                entry.stats = self.get(&path);
+            }
+        }
+    }
+
+    // Count again the totals a change to `path` shows up in: its own, those of the folders
+    // inside it and those of the folders containing it
+    pub fn invalidate(&self, path: &Path) {
+        let mut counted = self.counted.lock().unwrap();
+        for (folder, entry) in counted.iter_mut() {
+            if folder.starts_with(path) || path.starts_with(folder) {
+                entry.stale = true;
+            }
+        }
+    }
+
+    fn enqueue(&self, folder: PathBuf) {
+        let mut queue = self.queue.lock().unwrap();
+        let sender = queue.get_or_insert_with(|| {
+            let (sender, receiver) = channel::<PathBuf>();
+            let counted = self.counted.clone();
+            let show_hidden = self.show_hidden;
+            thread::spawn(move || {
+                for folder in receiver {
+                    let stats = count(&folder, show_hidden).ok();
+                    let mut counted = counted.lock().unwrap();
+                    // Folders not listed for a while are forgotten
+                    counted.retain(|_, c| c.queued || c.at.elapsed() <= STATS_TTL);
+                    counted.insert(
+                        folder,
+                        Counted {
+                            stats,
+                            at: Instant::now(),
+                            stale: false,
+                            queued: false,
+                        },
+                    );
+                }
+            });
+            sender
+        });
+        sender.send(folder).ok();
+    }
+}
+
+// Walk `folder`, without following links to folders
+pub fn count(folder: &Path, show_hidden: bool) -> std::io::Result<FolderStats> {
+    let mut stats = FolderStats::default();
+    add(folder, show_hidden, &mut stats)?;
+    if stats.newest > 0 {
+        stats.newest_string = Utc
+            .timestamp_opt(stats.newest as i64, 0)
+            .unwrap()
+            .format("%Y-%m-%d")
+            .to_string();
+    }
+    Ok(stats)
+}
+
+fn add(folder: &Path, show_hidden: bool, stats: &mut FolderStats) -> std::io::Result<()> {
+    for entry in fs::read_dir(folder)?.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == ".th" || (!show_hidden && name.starts_with('.')) {
+            continue;
+        }
+
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+        if file_type.is_dir() {
+            // Unreadable sub folders are left out
+            add(&entry.path(), show_hidden, stats).ok();
+            continue;
+        }
+        let meta = match fs::metadata(entry.path()) {
+            Ok(meta) if meta.is_file() => meta,
+            _ => continue,
+        };
+        stats.files += 1;
+        stats.bytes += meta.len();
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        stats.newest = stats.newest.max(modified);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROOT: &str = "./test-fixtures/";
+
+    #[test]
+    fn test_count() {
+        let stats = count(&Path::new(ROOT).join("imgs"), false).unwrap();
+        // Four images, and three files in subimg; thumbnails are not counted
+        assert_eq!(stats.files, 7);
+        assert!(stats.bytes > 0);
+        assert!(stats.newest > 0);
+        assert!(!stats.newest_string.is_empty());
+    }
+
+    #[test]
+    fn test_background() {
+        let cache = FolderStatsCache::new(false, Arc::default());
+        let folder = Path::new(ROOT).join("imgs/subimg");
+        assert_eq!(cache.get(&folder), None);
+
+        let mut stats = None;
+        for _ in 0..100 {
+            stats = cache.get(&folder);
+            if stats.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(stats.map(|s| s.files), Some(3));
+
+        // Known totals are kept while they are counted again
+        cache.invalidate(&folder);
+        assert!(cache.get(&folder).is_some());
+
+        // Expired totals of other folders are dropped with the next count
+        cache.counted.lock().unwrap().insert(
+            PathBuf::from("/gone"),
+            Counted {
+                stats: None,
+                at: Instant::now() - STATS_TTL * 2,
+                stale: false,
+                queued: false,
+            },
+        );
+        cache.invalidate(&folder);
+        cache.get(&folder);
+        for _ in 0..100 {
+            if !cache
+                .counted
+                .lock()
+                .unwrap()
+                .contains_key(Path::new("/gone"))
+            {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(!cache
+            .counted
+            .lock()
+            .unwrap()
+            .contains_key(Path::new("/gone")));
+    }
+
+    #[test]
+    fn test_restricted() {
+        let imgs = Path::new(ROOT).join("imgs");
+        let cache = FolderStatsCache::new(false, Arc::new(vec![imgs.join("subimg")]));
+        assert_eq!(cache.get(&imgs), None);
+        assert_eq!(cache.get(Path::new(ROOT)), None);
+        assert!(cache.counted.lock().unwrap().is_empty());
+        // A rule on the folder itself applies to all of it
+        cache.get(&imgs.join("subimg"));
+        assert_eq!(cache.counted.lock().unwrap().len(), 1);
+    }
+}
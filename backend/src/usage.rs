@@ -0,0 +1,307 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    get, web, Error, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::acl;
+use crate::auth::Identity;
+use crate::check_read;
+use crate::config::Hidden;
+use crate::dir::encode_path;
+use crate::jobs::{Job, Jobs};
+use crate::state::AppState;
+
+// Finished scans are answered from memory for this long
+pub(crate) const USAGE_TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_DEPTH: usize = 8;
+const MAX_TOP: usize = 100;
+
+#[derive(Deserialize, Debug)]
+pub struct UsageQuery {
+    // Levels of sub folders to break down
+    #[serde(default = "default_depth")]
+    depth: usize,
+    // Largest children kept per folder, the rest are added up in `other_*`
+    #[serde(default = "default_top")]
+    top: usize,
+    // Scan again, even if a recent scan is known
+    #[serde(default)]
+    refresh: bool,
+}
+
+fn default_depth() -> usize {
+    3
+}
+
+fn default_top() -> usize {
+    12
+}
+
+// A file or folder with the space it takes, largest children first
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct UsageNode {
+    name: String,
+    path: PathBuf,
+    folder: bool,
+    bytes: u64,
+    files: u64,
+    other_bytes: u64,
+    other_files: u64,
+    children: Vec<UsageNode>,
+}
+
+impl UsageNode {
+    fn fold(&mut self, child: &UsageNode) {
+        self.other_bytes += child.bytes;
+        self.other_files += child.files;
+    }
+
+    // Copy broken down `depth` levels, with the `top` largest children of each folder
+    // (children must be the largest first, as scanned)
+    fn trim(&self, depth: usize, top: usize) -> UsageNode {
+        let keep = if depth == 0 { 0 } else { top };
+        let mut node = UsageNode {
+            name: self.name.clone(),
+            path: self.path.clone(),
+            children: vec![],
+            ..*self
+        };
+        for child in self.children.iter().skip(keep) {
+            node.fold(child);
+        }
+        node.children = self
+            .children
+            .iter()
+            .take(keep)
+            .map(|c| c.trim(depth.saturating_sub(1), top))
+            .collect();
+        node
+    }
+}
+
+// Result of a scan
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Usage {
+    tree: UsageNode,
+}
+
+// Scans by user (see acl::scan_owner), library and folder, as deep and wide as can be
+// asked for, then trimmed for each request; paths in the tree are under the library,
+// and nested library roots share folders
+pub type UsageJobs = Jobs<(Option<String>, String, PathBuf), Usage>;
+
+struct Scan<'a> {
+    root: PathBuf,
+    name: String,
+    hidden: Hidden,
+    top: usize,
+    job: &'a Job<Usage>,
+    // Whether the user can see a path (and whether it is a folder), see acl::scan_filter
+    visible: &'a dyn Fn(&Path, bool) -> bool,
+}
+
+impl Scan<'_> {
+    // Tree of `path`, broken down `depth` more levels
+    // Links are not followed, unreadable folders count as empty and what the user
+    // can't see isn't counted at all
+    fn node(&self, path: &Path, depth: usize) -> UsageNode {
+        let mut node = UsageNode {
+            name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            path: self.url_path(path),
+            folder: true,
+            ..UsageNode::default()
+        };
+
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return node,
+        };
+        let mut children = vec![];
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if self.hides(&name.to_string_lossy()) {
+                continue;
+            }
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            if !(self.visible)(&entry.path(), file_type.is_dir()) {
+                continue;
+            }
+            let child = if file_type.is_dir() {
+                self.node(&entry.path(), depth.saturating_sub(1))
+            } else if file_type.is_file() {
+                let bytes = entry.metadata().map(|m| m.len()).unwrap_or_default();
+                self.job.add(bytes);
+                UsageNode {
+                    name: name.to_string_lossy().to_string(),
+                    path: self.url_path(&entry.path()),
+                    bytes,
+                    files: 1,
+                    ..UsageNode::default()
+                }
+            } else {
+                continue;
+            };
+            node.bytes += child.bytes;
+            node.files += child.files;
+            children.push(child);
+        }
+
+        children.sort_by_key(|c| std::cmp::Reverse(c.bytes));
+        let keep = if depth == 0 { 0 } else { self.top };
+        for child in children.iter().skip(keep) {
+            node.fold(child);
+        }
+        children.truncate(keep);
+        node.children = children;
+        node
+    }
+
+    // Path as in listings: under the library name, with names encoded (see encode_path)
+    fn url_path(&self, path: &Path) -> PathBuf {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let relative = encode_path(relative).unwrap_or_default();
+        if relative.as_os_str().is_empty() {
+            PathBuf::from(&self.name)
+        } else {
+            Path::new(&self.name).join(relative)
+        }
+    }
+
+    // Thumbnails take space too, so only dot files hidden by the library are left out
+    fn hides(&self, name: &str) -> bool {
+        self.hidden == Hidden::Hide && name.starts_with('.') && name != ".th"
+    }
+}
+
+// Space taken by a folder, scanned in the background: answers 202 with the progress
+// until the scan is done, then 200 with the tree
+#[get("/_api/usage/{file:.*}")]
+pub async fn usage(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<UsageQuery>,
+    identity: Option<web::ReqData<Identity>>,
+) -> Result<HttpResponse, Error> {
+    let identity = identity.map(|i| i.into_inner());
+    let (library, path) = state.resolve(req.match_info().query("file"))?;
+    check_read(&state, identity.as_ref(), library, &path)?;
+    if !path.is_dir() {
+        return Err(ErrorBadRequest("Not a folder"));
+    }
+
+    let depth = query.depth.min(MAX_DEPTH);
+    let top = query.top.clamp(1, MAX_TOP);
+    let (root, name, hidden) = (library.root.clone(), library.name.clone(), library.hidden);
+    let owner = acl::scan_owner(&state, identity.as_ref());
+    let key = (owner, name.clone(), path.clone());
+    let visible = acl::scan_filter(&state, identity.as_ref(), library);
+    let job = state.usage.start(key, query.refresh, move |job| {
+        let scan = Scan {
+            root,
+            name,
+            hidden,
+            top: MAX_TOP,
+            job,
+            visible: &visible,
+        };
+        Usage {
+            tree: scan.node(&path, MAX_DEPTH),
+        }
+    });
+
+    if job.failed() {
+        return Err(ErrorInternalServerError("Scan failed"));
+    }
+    let mut progress = job.progress();
+    if let Some(usage) = progress.result.as_mut() {
+        usage.tree = usage.tree.trim(depth, top);
+    }
+    if progress.done {
+        Ok(HttpResponse::Ok().json(progress))
+    } else {
+        Ok(HttpResponse::Accepted().json(progress))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROOT: &str = "./test-fixtures/";
+
+    #[test]
+    fn test_node() {
+        let job = Job::default();
+        let scan = Scan {
+            root: PathBuf::from(ROOT),
+            name: String::new(),
+            hidden: Hidden::Show,
+            top: 2,
+            job: &job,
+            visible: &|_, _| true,
+        };
+        let tree = scan.node(&Path::new(ROOT).join("imgs"), 1);
+        assert_eq!(tree.path, PathBuf::from("imgs"));
+        assert_eq!(tree.children.len(), 2);
+        assert!(tree.children[0].bytes >= tree.children[1].bytes);
+        // Too deep to break down, only added up
+        assert!(tree.children.iter().all(|c| c.children.is_empty()));
+
+        let kept: u64 = tree.children.iter().map(|c| c.bytes).sum();
+        assert_eq!(kept + tree.other_bytes, tree.bytes);
+        let progress = job.progress();
+        assert_eq!((progress.files, progress.bytes), (tree.files, tree.bytes));
+
+        // What can't be seen isn't counted, not even in the progress
+        let job = Job::default();
+        let scan = Scan {
+            job: &job,
+            visible: &|path, _| !path.ends_with("subimg"),
+            ..scan
+        };
+        let hidden = scan.node(&Path::new(ROOT).join("imgs"), 1);
+        assert!(hidden.children.iter().all(|c| c.name != "subimg"));
+        assert!(hidden.bytes < tree.bytes);
+        assert_eq!(job.progress().bytes, hidden.bytes);
+    }
+
+    #[test]
+    fn test_trim() {
+        let job = Job::default();
+        let scan = Scan {
+            root: PathBuf::from(ROOT),
+            name: String::new(),
+            hidden: Hidden::Show,
+            top: MAX_TOP,
+            job: &job,
+            visible: &|_, _| true,
+        };
+        let full = scan.node(Path::new(ROOT), MAX_DEPTH);
+        // The same as scanning for the breakdown asked for
+        for (depth, top) in [(0, 1), (1, 2), (2, 1), (3, 12)] {
+            let scan = Scan {
+                root: PathBuf::from(ROOT),
+                name: String::new(),
+                hidden: Hidden::Show,
+                top,
+                job: &job,
+                visible: &|_, _| true,
+            };
+            assert_eq!(
+                serde_json::to_value(full.trim(depth, top)).unwrap(),
+                serde_json::to_value(scan.node(Path::new(ROOT), depth)).unwrap()
+            );
+        }
+    }
+}
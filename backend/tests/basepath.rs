@@ -0,0 +1,126 @@
+// Deployments at the root and under a --basepath, mounted like the binary does
+
+use actix_web::{
+    dev::Service,
+    http::{header, StatusCode},
+    test, web, App,
+};
+use serde_json::{json, Value};
+use structopt::StructOpt;
+
+use hview_backend::{auth, AppState, Config, Dir};
+
+// The embedded frontend moves the endpoints under "api/"
+const API: &str = if cfg!(feature = "embed-frontend") {
+    "api/"
+} else {
+    ""
+};
+
+fn state(args: &[&str]) -> web::Data<AppState> {
+    let args = ["hview", "-d", "./test-fixtures/", "-n"].iter().chain(args);
+    web::Data::new(AppState::new(Config::from_iter(args)).unwrap())
+}
+
+async fn check_deployment(basepath: &str) {
+    let state = state(&["--basepath", basepath]);
+    let mut app =
+        test::init_service(App::new().configure(|cfg| hview_backend::mount(cfg, state))).await;
+
+    let req = test::TestRequest::get()
+        .uri(&format!("{}{}imgs/", basepath, API))
+        .to_request();
+    let dir: Dir = test::read_response_json(&mut app, req).await;
+    assert_eq!(dir.title, "imgs");
+    assert_eq!(dir.base_path, "/", "paths are relative to the mount");
+
+    let req = test::TestRequest::get()
+        .uri(&format!("{}{}imgs/flower.jpg", basepath, API))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "image/jpeg"
+    );
+
+    let req = test::TestRequest::post()
+        .uri(&format!("{}{}_api/shares", basepath, API))
+        .set_json(&json!({ "path": "imgs" }))
+        .to_request();
+    let share: Value = test::read_response_json(&mut app, req).await;
+    let token = share["token"].as_str().unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("{}{}_s/{}/", basepath, API, token))
+        .to_request();
+    let dir: Dir = test::read_response_json(&mut app, req).await;
+    assert_eq!(dir.base_path, format!("/_s/{}/", token));
+    assert_eq!(
+        dir.folders[0].path,
+        std::path::PathBuf::from(format!("_s/{}/subimg", token))
+    );
+}
+
+#[actix_rt::test]
+async fn test_root() {
+    check_deployment("/").await;
+}
+
+#[actix_rt::test]
+async fn test_sub() {
+    check_deployment("/sub/").await;
+
+    let state = state(&["--basepath", "/sub/"]);
+    let mut app =
+        test::init_service(App::new().configure(|cfg| hview_backend::mount(cfg, state))).await;
+    let req = test::TestRequest::get()
+        .uri(&format!("/{}imgs/", API))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn test_sub_login() {
+    let dir = tempfile::tempdir().unwrap();
+    let users = dir.path().join("users.txt");
+    let hash = auth::hash_password("secret").unwrap();
+    std::fs::write(&users, format!("alice:{}\n", hash)).unwrap();
+
+    let state = state(&["--basepath", "/sub/", "--users", users.to_str().unwrap()]);
+    let mut app =
+        test::init_service(App::new().configure(|cfg| hview_backend::mount(cfg, state))).await;
+
+    // The middleware rejects requests with an error, turned into a response by the server
+    let req = test::TestRequest::get()
+        .uri(&format!("/sub/{}imgs/", API))
+        .to_request();
+    let error = app.call(req).await.err().unwrap();
+    assert_eq!(
+        error.as_response_error().status_code(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Public paths are recognized under the basepath
+    let req = test::TestRequest::get()
+        .uri(&format!("/sub/{}_s/nope/", API))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/sub/{}_api/login", API))
+        .set_json(&json!({ "username": "alice", "password": "secret" }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cookie = resp.response().cookies().next().unwrap().into_owned();
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/sub/{}imgs/", API))
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
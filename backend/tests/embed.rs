@@ -0,0 +1,46 @@
+// Single binary mode, needs a frontend build in ../frontend/dist
+#![cfg(feature = "embed-frontend")]
+
+use actix_web::{
+    http::{header, StatusCode},
+    test, web, App,
+};
+use structopt::StructOpt;
+
+use hview_backend::{AppState, Config};
+
+#[actix_rt::test]
+async fn test_spa_fallback() {
+    let args = [
+        "hview",
+        "-d",
+        "./test-fixtures/",
+        "-n",
+        "--basepath",
+        "/sub/",
+    ];
+    let state = web::Data::new(AppState::new(Config::from_iter(&args)).unwrap());
+    let mut app =
+        test::init_service(App::new().configure(|cfg| hview_backend::mount(cfg, state))).await;
+
+    // App routes get index.html, which handles them in the browser
+    for uri in &["/sub/", "/sub/imgs/flower.jpg", "/sub/_login/imgs/"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+        let content_type = resp.headers().get(header::CONTENT_TYPE).unwrap();
+        assert!(content_type.to_str().unwrap().starts_with("text/html"));
+    }
+
+    let req = test::TestRequest::get().uri("/sub/api/imgs/").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+
+    // Missing API paths are not sent to the app
+    let req = test::TestRequest::get().uri("/sub/api/nope").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
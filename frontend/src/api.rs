@@ -0,0 +1,30 @@
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+use yew::format::{Json, Text};
+use yew::services::fetch::StatusCode;
+
+// JSON body of backend errors, eg {"error": "not_found", "message": "Not found"}
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    message: String,
+}
+
+// Error for a failed API response, with the message sent by the backend when there is one
+pub fn response_error(status: StatusCode, body: Text) -> Error {
+    let text = match body {
+        Ok(text) if !text.trim().is_empty() => text,
+        _ => {
+            return anyhow!(
+                "{} ({})",
+                status.canonical_reason().unwrap_or("Error"),
+                status.as_str()
+            )
+        }
+    };
+
+    match Json::<Result<ErrorBody, Error>>::from(Ok(text.to_owned())) {
+        Json(Ok(body)) => anyhow!(body.message),
+        // Plain text errors (eg from access control)
+        _ => anyhow!(text),
+    }
+}
@@ -0,0 +1,372 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use yew::format::{Json, Nothing, Text};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask, Request, Response};
+use yew::services::timeout::{TimeoutService, TimeoutTask};
+use yew::services::ConsoleService;
+use yew::Properties;
+
+use super::entry::EntryProps;
+use super::page::BatchResult;
+use super::usage::human_size;
+use crate::api::response_error;
+use crate::{App, AppAnchor, SERVER_URL};
+use anyhow::{anyhow, Error};
+
+// Files with the same content
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DuplicateSet {
+    size: u64,
+    hash: String,
+    files: Vec<EntryProps>,
+}
+
+// Progress of the scan, and the sets once done
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct DupesReport {
+    done: bool,
+    files: u64,
+    bytes: u64,
+    #[serde(default)]
+    sets: Vec<DuplicateSet>,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct DeleteRequest<'a> {
+    paths: &'a [String],
+}
+
+pub enum DupesMsg {
+    Report(DupesReport),
+    Poll,
+    Refresh,
+    // Set (by hash) and path of the copy to keep
+    Keep(String, String),
+    // Delete every copy of a set but the kept one
+    DeleteCopies(String),
+    Deleted(BatchResult),
+    Error(Error),
+    Unauthorized,
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct DupesProps {
+    pub path: String,
+}
+
+// Review of the duplicate files under a folder, found in the background by the backend
+pub struct Dupes {
+    link: ComponentLink<Self>,
+    props: DupesProps,
+    task: Option<FetchTask>,
+    poll_task: Option<TimeoutTask>,
+    delete_task: Option<FetchTask>,
+    report: Option<DupesReport>,
+    // Copy to keep per set (by hash), the first one unless picked
+    keep: Vec<(String, String)>,
+    error: Option<Error>,
+}
+
+impl Component for Dupes {
+    type Message = DupesMsg;
+    type Properties = DupesProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut dupes = Self {
+            link,
+            props,
+            task: None,
+            poll_task: None,
+            delete_task: None,
+            report: None,
+            keep: Vec::new(),
+            error: None,
+        };
+        dupes.task = dupes.fetch_dupes(false);
+        dupes
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            DupesMsg::Report(report) => {
+                self.task = None;
+                self.error = None;
+                // Ask again until the scan is done
+                self.poll_task = if report.done {
+                    None
+                } else {
+                    let poll = self.link.callback(|_| DupesMsg::Poll);
+                    Some(TimeoutService::spawn(Duration::from_secs(1), poll))
+                };
+                self.report = Some(report);
+                true
+            }
+            DupesMsg::Poll => {
+                self.task = self.fetch_dupes(false);
+                false
+            }
+            DupesMsg::Refresh => {
+                self.report = None;
+                self.keep.clear();
+                self.task = self.fetch_dupes(true);
+                true
+            }
+            DupesMsg::Keep(hash, path) => {
+                self.keep.retain(|(h, _)| h != &hash);
+                self.keep.push((hash, path));
+                true
+            }
+            DupesMsg::DeleteCopies(hash) => {
+                let set = match self.set(&hash) {
+                    Some(set) => set,
+                    None => return false,
+                };
+                let keep = self.kept(set).to_owned();
+                let paths: Vec<String> = set
+                    .files
+                    .iter()
+                    .map(|f| f.path.to_owned())
+                    .filter(|p| p != &keep)
+                    .collect();
+                let window = web_sys::window().unwrap();
+                let message = format!("Delete {} copies, keeping {}?", paths.len(), keep);
+                if window.confirm_with_message(&message).unwrap_or(false) {
+                    self.delete_task = self.delete(&paths);
+                    true
+                } else {
+                    false
+                }
+            }
+            DupesMsg::Deleted(result) => {
+                self.delete_task = None;
+                // Drop deleted copies, and sets left with a single file
+                if let Some(report) = self.report.as_mut() {
+                    for set in report.sets.iter_mut() {
+                        set.files.retain(|f| !result.done.contains(&f.path));
+                    }
+                    report.sets.retain(|set| set.files.len() > 1);
+                }
+                self.error = if result.failed.is_empty() {
+                    None
+                } else {
+                    let failed = result
+                        .failed
+                        .iter()
+                        .map(|f| format!("{} ({})", f.path, f.error))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    Some(anyhow!("{} deleted, failed: {}", result.done.len(), failed))
+                };
+                true
+            }
+            DupesMsg::Unauthorized => {
+                App::replace_route(format!("/_login/_dupes/{}", &self.props.path));
+                false
+            }
+            DupesMsg::Error(error) => {
+                ConsoleService::error(format!("Invalid response: {:?}", error).as_str());
+                self.error = Some(error);
+                self.task = None;
+                self.poll_task = None;
+                self.delete_task = None;
+                true
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            self.props = props;
+            self.report = None;
+            self.keep.clear();
+            self.poll_task = None;
+            self.task = self.fetch_dupes(false);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let path = self.props.path.trim_matches('/');
+        App::set_title(format!("Duplicates: /{}", path));
+
+        let error = match &self.error {
+            Some(error) => html! {<h2 class="text-danger">{ "Error: " }{ error }</h2>},
+            None => html! {},
+        };
+
+        let content = match &self.report {
+            Some(report) if report.done && report.sets.is_empty() => {
+                html! {<p>{ "No duplicate files found" }</p>}
+            }
+            Some(report) if report.done => {
+                let wasted: u64 = report
+                    .sets
+                    .iter()
+                    .map(|s| s.size * (s.files.len() as u64 - 1))
+                    .sum();
+                let note = if report.read_only {
+                    html! {<p class="text-warning">{ "Read-only: copies can't be deleted here" }</p>}
+                } else {
+                    html! {}
+                };
+                html! {
+                    <>
+                        <p>{ report.sets.len() }{ " sets of duplicates, " }{ human_size(wasted) }{ " in extra copies" }</p>
+                        { note }
+                        { for report.sets.iter().map(|set| self.view_set(set, report.read_only)) }
+                    </>
+                }
+            }
+            Some(report) => html! {
+                <p>
+                    <span class="loading"></span>
+                    { "Comparing: " }{ report.files }{ " files, " }{ human_size(report.bytes) }
+                </p>
+            },
+            None => html! {<span class="loading"></span>},
+        };
+
+        html! {
+            <>
+                <h1 id="title">
+                    <AppAnchor route={ App::route(&format!("/{}/", path)) }>{ "/" }{ path }</AppAnchor>
+                    <button class="btn btn-sm btn-outline-light ms-2" disabled={ self.task.is_some() || self.poll_task.is_some() }
+                        onclick={ self.link.callback(|_| DupesMsg::Refresh) }>
+                        { "Scan again" }
+                    </button>
+                </h1>
+                { error }
+                { content }
+            </>
+        }
+    }
+}
+
+impl Dupes {
+    fn set(&self, hash: &str) -> Option<&DuplicateSet> {
+        self.report.as_ref()?.sets.iter().find(|s| s.hash == hash)
+    }
+
+    // Path of the copy of `set` to keep
+    fn kept<'a>(&'a self, set: &'a DuplicateSet) -> &'a str {
+        self.keep
+            .iter()
+            .find(|(hash, _)| hash == &set.hash)
+            .map(|(_, path)| path.as_str())
+            .filter(|path| set.files.iter().any(|f| &f.path == path))
+            .unwrap_or_else(|| set.files[0].path.as_str())
+    }
+
+    fn view_set(&self, set: &DuplicateSet, read_only: bool) -> Html {
+        let keep = self.kept(set);
+        let files = set.files.iter().map(|file| {
+            let thumb = match &file.thumb {
+                Some(thumb) => {
+                    let src = format!("{}{}", *SERVER_URL, thumb);
+                    html! {<img src={ src } loading="lazy" class="dupe-thumb" />}
+                }
+                None => html! {},
+            };
+            let (hash, path) = (set.hash.to_owned(), file.path.to_owned());
+            let onchange = self
+                .link
+                .callback(move |_| DupesMsg::Keep(hash.to_owned(), path.to_owned()));
+            html! {
+                <li class="list-group-item">
+                    <label>
+                        <input type="radio" class="form-check-input me-2" name={ set.hash.to_owned() }
+                            checked={ file.path == keep } disabled={ read_only } onchange={ onchange } />
+                        { thumb }
+                        <AppAnchor route={ App::route(&file.path) }>{ &file.path }</AppAnchor>
+                        <small class="ms-2">{ &file.date_string }</small>
+                    </label>
+                </li>
+            }
+        });
+
+        let hash = set.hash.to_owned();
+        let delete = if read_only {
+            html! {}
+        } else {
+            html! {
+                <button class="btn btn-sm btn-outline-danger" disabled={ self.delete_task.is_some() }
+                    onclick={ self.link.callback(move |_| DupesMsg::DeleteCopies(hash.to_owned())) }>
+                    { "Delete other copies" }
+                </button>
+            }
+        };
+
+        html! {
+            <section class="dupe-set mb-4">
+                <h5>{ set.files.len() }{ " copies of " }{ human_size(set.size) }{ " " }{ delete }</h5>
+                <ul class="list-group">{ for files }</ul>
+            </section>
+        }
+    }
+
+    fn fetch_dupes(&self, refresh: bool) -> Option<FetchTask> {
+        let url = format!(
+            "{}_api/duplicates/{}?refresh={}",
+            *SERVER_URL,
+            self.props.path.trim_matches('/'),
+            refresh
+        );
+        let request = Request::get(url.as_str())
+            .body(Nothing)
+            .expect("Could not load from API");
+        let callback = self
+            .link
+            .callback(|response: Response<Text>| {
+                let status = response.status();
+                if status.as_u16() == 401 {
+                    return DupesMsg::Unauthorized;
+                }
+                let body = response.into_body();
+                if !status.is_success() {
+                    return DupesMsg::Error(response_error(status, body));
+                }
+
+                let Json(data): Json<Result<DupesReport, Error>> = Json::from(body);
+                match data {
+                    Ok(report) => DupesMsg::Report(report),
+                    Err(err) => DupesMsg::Error(err),
+                }
+            });
+        let task = FetchService::fetch(request, callback).expect("Could not load duplicates");
+        Some(task)
+    }
+
+    fn delete(&self, paths: &[String]) -> Option<FetchTask> {
+        let body = DeleteRequest { paths };
+        let url = format!("{}_api/batch/delete", *SERVER_URL);
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(Json(&body))
+            .expect("Could not build delete request");
+        let callback = self
+            .link
+            .callback(|response: Response<Text>| {
+                let status = response.status();
+                if status.as_u16() == 401 {
+                    return DupesMsg::Unauthorized;
+                }
+                let body = response.into_body();
+                if !status.is_success() {
+                    return DupesMsg::Error(response_error(status, body));
+                }
+
+                let Json(data): Json<Result<BatchResult, Error>> = Json::from(body);
+                match data {
+                    Ok(result) => DupesMsg::Deleted(result),
+                    Err(err) => DupesMsg::Error(err),
+                }
+            });
+        let task = FetchService::fetch(request, callback).expect("Could not delete copies");
+        Some(task)
+    }
+}
@@ -1,9 +1,17 @@
-use crate::{AppAnchor, AppRoute, SERVER_URL};
+use super::reader::is_comic;
+use crate::{App, AppAnchor, SERVER_URL};
 use serde::Deserialize;
 use yew::html::IntoPropValue;
 use yew::prelude::*;
 use yew::Properties;
 
+// Archives can be browsed like folders
+pub const ARCHIVE_TYPES: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+pub fn is_archive(path: &str) -> bool {
+    let p = &path.to_lowercase();
+    ARCHIVE_TYPES.iter().any(|t| p.ends_with(t))
+}
+
 #[derive(Deserialize, Clone, Debug, PartialEq)]
 pub enum EntryType {
     File,
@@ -39,6 +47,14 @@ impl IntoPropValue<EntryType> for &str {
     }
 }
 
+// Totals of a folder and its sub folders, counted in the background by the backend
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct FolderStats {
+    pub files: u64,
+    pub bytes: u64,
+    pub newest_string: String,
+}
+
 #[derive(Properties, Deserialize, Debug, Clone, PartialEq)]
 pub struct EntryProps {
     pub name: String,
@@ -48,9 +64,27 @@ pub struct EntryProps {
     pub date_string: String,
     pub thumb: Option<String>,
     pub ext: Option<String>,
+    // Missing until counted
+    #[prop_or_default]
+    #[serde(default)]
+    pub stats: Option<FolderStats>,
     #[prop_or_default]
     #[serde(skip)]
     pub etype: EntryType,
+    #[prop_or_default]
+    #[serde(skip)]
+    pub selecting: bool,
+    #[prop_or_default]
+    #[serde(skip)]
+    pub selected: bool,
+    // Emits (path, shift held) when the selection checkbox is clicked
+    #[prop_or_default]
+    #[serde(skip)]
+    pub onselect: Callback<(String, bool)>,
+    // Share links can disable downloads
+    #[prop_or_default]
+    #[serde(skip)]
+    pub no_download: bool,
 }
 
 pub struct Entry {
@@ -84,16 +118,19 @@ impl Component for Entry {
         let etype: &str = p.etype.to_owned().into();
 
         // Ensure paths have proper trailing /
+        let comic = p.etype == EntryType::File && is_comic(&p.path);
+        let archive = p.etype == EntryType::File && (comic || is_archive(&p.path));
         let link = match &p.etype {
-            EntryType::File => format!("/{}", &p.path),
-            EntryType::Folder => format!("/{}/", &p.path),
+            EntryType::File if comic => format!("/_read/{}", &p.path),
+            EntryType::File if !archive => format!("/{}", &p.path),
+            _ => format!("/{}/", &p.path),
         };
 
         let thumb = if let Some(thumb) = &p.thumb {
             let src = format!("{}{}", *SERVER_URL, &thumb);
             html! {
             <>
-                <AppAnchor classes={ etype } route=AppRoute::Entry(link.to_owned())>
+                <AppAnchor classes={ etype } route={ App::route(&link) }>
                     <img src={ src } loading="lazy" class="thumb pb-3" />
                 </AppAnchor><br />
             </>
@@ -103,22 +140,58 @@ impl Component for Entry {
         };
 
         let icon = match &p.etype {
+            EntryType::File if comic => classes!("bi", "bi-book", "text-warning"),
+            EntryType::File if archive => classes!("bi", "bi-file-zip", "text-warning"),
             EntryType::File => classes!("bi", "bi-file-richtext", "text-success"),
             EntryType::Folder => classes!("bi", "bi-folder-fill", "text-info"),
         };
 
+        // Archive links browse inside, so offer the download separately
+        let download = if archive && !p.no_download {
+            let href = format!("{}{}", *SERVER_URL, &p.path);
+            html! {
+                <a href={ href } download="" class="ms-2" title="Download">
+                    <i class="bi bi-download"></i>
+                </a>
+            }
+        } else {
+            html! {}
+        };
+
         let size = match &p.etype {
             EntryType::File => html! {<small>{ &p.size/1000 }{ "KiB" }{" / "}</small>},
-            EntryType::Folder => html! {},
+            EntryType::Folder => match &p.stats {
+                Some(stats) => {
+                    let newest = format!("Newest file: {}", stats.newest_string);
+                    html! {<small title={ newest }>{ stats.files }{ " files, " }{ stats.bytes/1000 }{ "KiB" }{" / "}</small>}
+                }
+                None => html! {},
+            },
         };
 
+        let select = if p.selecting {
+            let path = p.path.to_owned();
+            let onclick = p
+                .onselect
+                .reform(move |e: MouseEvent| (path.to_owned(), e.shift_key()));
+            html! {
+                <input type="checkbox" class="form-check-input select" checked={ p.selected } onclick={ onclick } />
+            }
+        } else {
+            html! {}
+        };
+
+        let selected = if p.selected { Some("selected") } else { None };
+
         html! {
-            <section class=classes!("col-sm-12","col-md-6","col-lg-3","mb-sm-2","mb-lg-5","text-break", etype)>
+            <section class=classes!("col-sm-12","col-md-6","col-lg-3","mb-sm-2","mb-lg-5","text-break", etype, selected)>
+                { select }
                 { thumb }
-                <AppAnchor classes={ etype } route=AppRoute::Entry(link)>
+                <AppAnchor classes={ etype } route={ App::route(&link) }>
                     <i class={ icon }></i>
                     <strong>{" "}{ &p.name }</strong>
-                </AppAnchor><br />
+                </AppAnchor>
+                { download }<br />
                 { size }
                 <small><time datetime={ p.date_string.to_owned() }>{ &p.date_string }</time></small>
             </section>
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use yew::format::{Json, Text};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask, Request, Response};
+use yew::services::ConsoleService;
+use yew::Properties;
+
+use crate::{App, SERVER_URL};
+use anyhow::{anyhow, Error};
+
+#[derive(Serialize, Debug)]
+struct LoginRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct UnlockRequest<'a> {
+    password: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Identity {
+    name: String,
+}
+
+pub enum LoginMsg {
+    Username(String),
+    Password(String),
+    Submit,
+    Done(Identity),
+    Unlocked,
+    Error(Error),
+}
+
+// Token of the share link a path belongs to ("/_s/<token>/..." or "/_read/_s/<token>/...")
+fn share_token(path: &str) -> Option<&str> {
+    let path = path.strip_prefix("/_read").unwrap_or(path);
+    let path = path.strip_prefix("/_s/")?;
+    path.split('/').next().filter(|t| !t.is_empty())
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct LoginProps {
+    // Path to return to after logging in
+    pub next: String,
+}
+
+pub struct Login {
+    link: ComponentLink<Self>,
+    props: LoginProps,
+    task: Option<FetchTask>,
+    error: Option<Error>,
+
+    username: String,
+    password: String,
+}
+
+impl Component for Login {
+    type Message = LoginMsg;
+    type Properties = LoginProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        Self {
+            link,
+            props,
+            task: None,
+            error: None,
+            username: String::new(),
+            password: String::new(),
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            LoginMsg::Username(username) => {
+                self.username = username;
+                false
+            }
+            LoginMsg::Password(password) => {
+                self.password = password;
+                false
+            }
+            LoginMsg::Submit => {
+                self.task = match share_token(&self.props.next) {
+                    Some(token) => self.unlock(token),
+                    None => self.login(),
+                };
+                true
+            }
+            LoginMsg::Done(identity) => {
+                ConsoleService::info(format!("Logged in as: {:?}", identity.name).as_str());
+                self.task = None;
+                self.next();
+                false
+            }
+            LoginMsg::Unlocked => {
+                self.task = None;
+                self.next();
+                false
+            }
+            LoginMsg::Error(error) => {
+                self.task = None;
+                self.error = Some(error);
+                self.password.clear();
+                true
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            self.props = props;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let onsubmit = self.link.callback(|e: FocusEvent| {
+            e.prevent_default();
+            LoginMsg::Submit
+        });
+        let username = self
+            .link
+            .callback(|e: InputData| LoginMsg::Username(e.value));
+        let password = self
+            .link
+            .callback(|e: InputData| LoginMsg::Password(e.value));
+
+        let error = if let Some(error) = &self.error {
+            html! {<p class="text-danger">{ error }</p>}
+        } else {
+            html! {}
+        };
+
+        // Share links only ask for the password of the share
+        let share = share_token(&self.props.next).is_some();
+        let username = if share {
+            html! {<p>{ "This link is password protected" }</p>}
+        } else {
+            html! {
+                <input class="form-control mb-3" type="text" placeholder="Username"
+                    autocomplete="username" value={ self.username.to_owned() } oninput={ username } />
+            }
+        };
+
+        html! {
+            <form id="login" class="mt-5" onsubmit={ onsubmit }>
+                <h1>{ "hview" }</h1>
+                { error }
+                { username }
+                <input class="form-control mb-3" type="password" placeholder="Password"
+                    autocomplete="current-password" value={ self.password.to_owned() } oninput={ password } />
+                <button class="btn btn-primary" type="submit" disabled={ self.task.is_some() }>
+                    { if share { "Open" } else { "Log in" } }
+                </button>
+            </form>
+        }
+    }
+
+    fn rendered(&mut self, first_render: bool) {
+        if first_render {
+            App::set_title(String::from("Log in"));
+        }
+    }
+}
+
+impl Login {
+    // Return to the page that required logging in
+    fn next(&self) {
+        let next = if self.props.next.is_empty() {
+            String::from("/")
+        } else {
+            self.props.next.to_owned()
+        };
+        App::replace_route(next);
+    }
+
+    fn unlock(&self, token: &str) -> Option<FetchTask> {
+        let url = format!("{}_api/unlock/{}", *SERVER_URL, token);
+        let body = UnlockRequest {
+            password: &self.password,
+        };
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(Json(&body))
+            .expect("Could not build unlock request");
+        let callback = self.link.callback(|response: Response<Text>| {
+            let status = response.status();
+            if status.as_u16() == 401 {
+                LoginMsg::Error(anyhow!("Invalid password"))
+            } else if !status.is_success() {
+                LoginMsg::Error(anyhow!(
+                    "Error: {} ({})",
+                    &status.canonical_reason().unwrap(),
+                    &status.as_str()
+                ))
+            } else {
+                LoginMsg::Unlocked
+            }
+        });
+        let task = FetchService::fetch(request, callback).expect("Could not unlock share");
+        Some(task)
+    }
+
+    fn login(&self) -> Option<FetchTask> {
+        let url = format!("{}_api/login", *SERVER_URL);
+        let body = LoginRequest {
+            username: &self.username,
+            password: &self.password,
+        };
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(Json(&body))
+            .expect("Could not build login request");
+        let callback = self
+            .link
+            .callback(|response: Response<Json<Result<Identity, Error>>>| {
+                let status = response.status();
+                if status.as_u16() == 401 {
+                    return LoginMsg::Error(anyhow!("Invalid username or password"));
+                } else if !status.is_success() {
+                    return LoginMsg::Error(anyhow!(
+                        "Error: {} ({})",
+                        &status.canonical_reason().unwrap(),
+                        &status.as_str()
+                    ));
+                }
+
+                let Json(data) = response.into_body();
+                match data {
+                    Ok(identity) => LoginMsg::Done(identity),
+                    Err(err) => LoginMsg::Error(err),
+                }
+            });
+        let task = FetchService::fetch(request, callback).expect("Could not log in");
+        Some(task)
+    }
+}
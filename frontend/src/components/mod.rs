@@ -1,3 +1,8 @@
+pub mod dupes;
 pub mod entry;
+pub mod login;
 pub mod modal;
 pub mod page;
+pub mod reader;
+pub mod similar;
+pub mod usage;
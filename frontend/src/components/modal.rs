@@ -1,3 +1,4 @@
+use serde::Deserialize;
 use yew::html::IntoPropValue;
 use yew::prelude::*;
 use yew::services::ConsoleService;
@@ -53,6 +54,15 @@ pub enum MediaType {
 }
 
 impl MediaType {
+    // Audio plays in the video element too
+    pub fn from_kind(kind: &str) -> Self {
+        match kind {
+            "image" => Self::Image,
+            "video" | "audio" => Self::Video,
+            _ => Self::None,
+        }
+    }
+
     pub fn from_path(media: &str) -> Self {
         if media == "" {
             Self::None
@@ -86,11 +96,55 @@ impl Into<&str> for MediaType {
     }
 }
 
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+// Details of a single file, from the stat endpoint
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct FileStat {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub date_string: String,
+    pub mime: Option<String>,
+    pub kind: String,
+    pub dimensions: Option<Dimensions>,
+    pub duration: Option<f64>,
+}
+
+impl FileStat {
+    // Whether the stat describes the page at `path` (with or without leading '/')
+    pub fn is_for(&self, path: &str) -> bool {
+        self.path.trim_start_matches('/') == path.trim_start_matches('/')
+    }
+
+    // One line summary, eg "flower.jpg · 1920×1080 · 2021-01-01"
+    fn summary(&self) -> String {
+        let mut parts = vec![self.name.to_owned()];
+        if let Some(d) = &self.dimensions {
+            parts.push(format!("{}×{}", d.width, d.height));
+        }
+        if let Some(duration) = self.duration {
+            let seconds = duration.round() as u64;
+            parts.push(format!("{}:{:02}", seconds / 60, seconds % 60));
+        }
+        if !self.date_string.is_empty() {
+            parts.push(self.date_string.to_owned());
+        }
+        parts.join(" · ")
+    }
+}
+
 pub enum ModalMsg {
     // Show,
     Hide,
     Next,
     Previous,
+    SetCover,
+    Similar,
     None,
 }
 
@@ -98,6 +152,14 @@ pub enum ModalMsg {
 pub struct ModalProps {
     pub src: String,
     pub media: MediaType,
+    #[prop_or_default]
+    pub stat: Option<FileStat>,
+    // Whether the folder of the file can be changed, eg its cover
+    #[prop_or_default]
+    pub editable: bool,
+    // Whether images alike can be looked up across the library, not in shares
+    #[prop_or_default]
+    pub similar: bool,
 }
 
 impl Default for ModalProps {
@@ -105,6 +167,9 @@ impl Default for ModalProps {
         ModalProps {
             src: String::from(""),
             media: MediaType::None,
+            stat: None,
+            editable: false,
+            similar: false,
         }
     }
 }
@@ -145,6 +210,20 @@ impl Component for Modal {
                     .downcast::<Page>()
                     .send_message(PageMsg::ModalPrevious);
             }
+            ModalMsg::SetCover => {
+                self.link
+                    .get_parent()
+                    .expect("Parent Comp")
+                    .clone()
+                    .downcast::<Page>()
+                    .send_message(PageMsg::SetCover(self.props.src.to_owned()));
+            }
+            ModalMsg::Similar => {
+                if let Some(instance) = &self.instance {
+                    instance.hide();
+                }
+                App::change_route(format!("/_similar/{}", self.props.src.trim_start_matches('/')));
+            }
             ModalMsg::Hide => {
                 // Hide by navigating to parent directory
                 if let Some(index) = &self.props.src.rfind('/') {
@@ -170,7 +249,7 @@ impl Component for Modal {
         let p = &self.props;
         ConsoleService::info(format!("Rendering Modal: {:?}", p.src).as_str());
 
-        let src = format!("{}{}", *SERVER_URL, p.src);
+        let src = format!("{}{}", *SERVER_URL, p.src.trim_start_matches('/'));
         let media = match p.media {
             MediaType::Image => {
                 let bg = format!("background-image:url('{}')", src);
@@ -189,6 +268,32 @@ impl Component for Modal {
                 html! {}
             }
         };
+        let info = match &p.stat {
+            Some(stat) if stat.is_for(&p.src) => {
+                let cover = if p.editable {
+                    html! {
+                        <button class="btn btn-sm btn-outline-light" onclick={ self.link.callback(|_| ModalMsg::SetCover) }>
+                            { "Set as folder cover" }
+                        </button>
+                    }
+                } else {
+                    html! {}
+                };
+                let similar = if p.similar && stat.kind == "image" {
+                    html! {
+                        <button class="btn btn-sm btn-outline-light" onclick={ self.link.callback(|_| ModalMsg::Similar) }>
+                            { "Similar images" }
+                        </button>
+                    }
+                } else {
+                    html! {}
+                };
+                html! {
+                    <div id="media_info">{ stat.summary() }{ cover }{ similar }</div>
+                }
+            }
+            _ => html! {},
+        };
 
         let onkeydown =
             self.link
@@ -205,7 +310,7 @@ impl Component for Modal {
             <div class="modal fade" id="media_modal" tabindex="-1" aria-hidden="true" data-bs-keyboard="false" onkeydown={ onkeydown }>
               <div class="modal-dialog modal-fullscreen">
                 <div class="modal-content">
-                  <div class="modal-body">{ media }</div>
+                  <div class="modal-body">{ media }{ info }</div>
                 </div>
               </div>
             </div>
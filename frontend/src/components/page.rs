@@ -1,13 +1,17 @@
-use serde::Deserialize;
-use yew::format::{Json, Nothing};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use wasm_bindgen::JsCast;
+use yew::format::{Json, Nothing, Text};
 use yew::prelude::*;
 use yew::services::fetch::{FetchService, FetchTask, Request, Response};
+use yew::services::timeout::{TimeoutService, TimeoutTask};
 use yew::services::ConsoleService;
 use yew::Properties;
 
 use super::entry::{Entry, EntryProps};
-use super::modal::{is_media, MediaType, Modal, ModalProps};
-use crate::{App, AppAnchor, AppRoute, SERVER_URL};
+use super::modal::{is_media, FileStat, MediaType, Modal, ModalProps};
+use crate::api::response_error;
+use crate::{App, AppAnchor, APP_BASE, SERVER_URL};
 use anyhow::{anyhow, Error};
 
 #[derive(Deserialize, Clone, PartialEq, Debug)]
@@ -15,18 +19,89 @@ pub struct Dir {
     title: String,
     base_path: String,
     read_only: bool,
+    shared: bool,
+    download: bool,
     files: Vec<EntryProps>,
     folders: Vec<EntryProps>,
 }
 
+// Answer to a page request: a folder listing, or the details of a file
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Listing {
+    Dir(Dir),
+    File(FileStat),
+}
+
+#[derive(Serialize, Debug)]
+struct BatchRequest<'a> {
+    paths: &'a [String],
+}
+
+#[derive(Serialize, Debug)]
+struct MoveRequest<'a> {
+    paths: &'a [String],
+    dest: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ShareRequest {
+    path: String,
+    expires_in: Option<u64>,
+    password: Option<String>,
+    download: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct CoverRequest<'a> {
+    path: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShareInfo {
+    token: String,
+}
+
+// Named root dir, for the library picker
+#[derive(Deserialize, Debug)]
+pub struct Library {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchResult {
+    pub done: Vec<String>,
+    pub failed: Vec<BatchFailure>,
+}
+
 #[derive(Debug)]
 pub enum PageMsg {
     Page(Dir),
-    File,
+    Stat(FileStat),
     Error(Error),
+    Unauthorized,
     Modal(String),
     ModalNext,
     ModalPrevious,
+    ToggleSelecting,
+    Select(String, bool),
+    SelectAll,
+    BulkDownload,
+    BulkDelete,
+    BulkMove,
+    BulkDone(BatchResult),
+    Share,
+    Shared(ShareInfo),
+    SetCover(String),
+    CoverSet,
+    Libraries(Vec<Library>),
+    Reload,
 }
 
 #[derive(Properties, Clone, PartialEq)]
@@ -41,9 +116,20 @@ pub struct Page {
     modal: ModalProps,
 
     task: Option<FetchTask>,
+    stat_task: Option<FetchTask>,
+    batch_task: Option<FetchTask>,
+    libraries_task: Option<FetchTask>,
+    reload_task: Option<TimeoutTask>,
     loaded: Option<String>,
     error: Option<Error>,
     show_loading: bool,
+
+    // Paths of selected entries, and the last one clicked (for shift ranges)
+    selecting: bool,
+    selected: Vec<String>,
+    anchor: Option<String>,
+
+    libraries: Vec<Library>,
 }
 
 impl Component for Page {
@@ -56,41 +142,85 @@ impl Component for Page {
             props,
             modal: ModalProps::default(),
             task: None,
+            stat_task: None,
+            batch_task: None,
+            libraries_task: None,
+            reload_task: None,
             loaded: None,
             error: None,
             show_loading: true,
+            selecting: false,
+            selected: Vec::new(),
+            anchor: None,
+            libraries: Vec::new(),
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
             PageMsg::Page(page) => {
+                // Folder totals are counted in the background, ask once more for missing ones
+                let counting = page.folders.iter().any(|f| f.stats.is_none());
+                let reloaded = matches!(&self.props.page, Some(p) if p.title == page.title);
+                self.reload_task = if counting && !reloaded {
+                    let reload = self.link.callback(|_| PageMsg::Reload);
+                    Some(TimeoutService::spawn(Duration::from_secs(2), reload))
+                } else {
+                    None
+                };
                 self.props.page = Some(page);
                 self.error = None;
                 self.show_loading = false;
                 true
             }
-            // TODO: This means non-media (non-modal display) files
-            // end up loading twice. Once on request,
-            // and then again on popup. Parent directy also reloads.
-            // Not sure the best solution at the moment.
-            // (Start with HEAD request instead of GET?)
-            PageMsg::File => {
-                let url = format!("{}{}", *SERVER_URL, &self.props.path);
-                web_sys::window()
-                    .unwrap()
-                    .open_with_url_and_target(&url, "_new_file")
-                    .unwrap();
-
-                self.show_loading = false;
+            PageMsg::Stat(stat) => {
+                self.stat_task = None;
+                if !stat.is_for(&self.props.path) {
+                    // Navigated elsewhere in the meantime
+                    return false;
+                }
                 self.error = None;
 
-                // Show containing dir by navigating to parent directory
-                if let Some(index) = &self.props.path.rfind('/') {
-                    let path = &self.props.path[0..index + 1];
-                    App::replace_route(path.to_string());
+                let path = self.props.path.to_owned();
+                let parent = parent(&path);
+                match MediaType::from_kind(&stat.kind) {
+                    MediaType::None if stat.kind == "folder" => {
+                        self.loaded = Some(path.to_owned());
+                        self.task = self.fetch_page(&path);
+                        true
+                    }
+                    MediaType::None => {
+                        // Other files open in a new tab, over the containing dir
+                        let url = format!("{}{}", *SERVER_URL, path.trim_start_matches('/'));
+                        web_sys::window()
+                            .unwrap()
+                            .open_with_url_and_target(&url, "_new_file")
+                            .unwrap();
+                        self.show_loading = false;
+                        App::replace_route(parent.to_string());
+                        false
+                    }
+                    media => {
+                        self.modal = ModalProps {
+                            src: path.to_owned(),
+                            media,
+                            stat: Some(stat),
+                            ..ModalProps::default()
+                        };
+                        self.show_loading = false;
+
+                        // Listing around the file, for next/previous
+                        if self.loaded.as_deref() != Some(parent) {
+                            self.loaded = Some(parent.to_string());
+                            self.task = self.fetch_page(parent);
+                        }
+                        true
+                    }
                 }
-
+            }
+            PageMsg::Unauthorized => {
+                // Log in, then come back to this path
+                App::replace_route(format!("/_login{}", &self.props.path));
                 false
             }
             PageMsg::Error(error) => {
@@ -106,6 +236,7 @@ impl Component for Page {
                 self.modal.src = src.to_string();
                 self.modal.media = MediaType::from_path(src.as_str());
                 self.show_loading = false;
+                self.stat_task = self.fetch_stat(&src);
                 true
             }
             PageMsg::ModalNext => {
@@ -118,6 +249,121 @@ impl Component for Page {
                 App::change_route(src);
                 true
             }
+            PageMsg::ToggleSelecting => {
+                self.selecting = !self.selecting;
+                self.clear_selection();
+                true
+            }
+            PageMsg::Select(path, range) => {
+                self.select(path, range);
+                true
+            }
+            PageMsg::SelectAll => {
+                if self.selected.len() == self.entry_paths().len() {
+                    self.selected.clear();
+                } else {
+                    self.selected = self.entry_paths();
+                }
+                true
+            }
+            PageMsg::BulkDownload => {
+                self.submit_download();
+                false
+            }
+            PageMsg::BulkDelete => {
+                let confirm = format!("Delete {} selected item(s)?", self.selected.len());
+                if web_sys::window()
+                    .unwrap()
+                    .confirm_with_message(&confirm)
+                    .unwrap_or(false)
+                {
+                    let body = BatchRequest {
+                        paths: &self.selected,
+                    };
+                    self.batch_task = self.post_batch("delete", Json(&body).into());
+                    self.show_loading = true;
+                }
+                true
+            }
+            PageMsg::BulkMove => {
+                let current = self
+                    .props
+                    .page
+                    .as_ref()
+                    .map(|p| p.title.to_owned())
+                    .unwrap_or_default();
+                if let Ok(Some(dest)) = web_sys::window()
+                    .unwrap()
+                    .prompt_with_message_and_default("Move selected item(s) to folder:", &current)
+                {
+                    let body = MoveRequest {
+                        paths: &self.selected,
+                        dest,
+                    };
+                    self.batch_task = self.post_batch("move", Json(&body).into());
+                    self.show_loading = true;
+                }
+                true
+            }
+            PageMsg::BulkDone(result) => {
+                self.batch_task = None;
+                self.clear_selection();
+                self.error = if result.failed.is_empty() {
+                    None
+                } else {
+                    let failed = result
+                        .failed
+                        .iter()
+                        .map(|f| format!("{} ({})", f.path, f.error))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    Some(anyhow!("{} done, failed: {}", result.done.len(), failed))
+                };
+
+                // Reload listing to reflect changes
+                if let Some(loaded) = self.loaded.to_owned() {
+                    self.task = self.fetch_page(&loaded);
+                }
+                true
+            }
+            PageMsg::Share => {
+                self.batch_task = self.create_share();
+                false
+            }
+            PageMsg::Shared(share) => {
+                self.batch_task = None;
+                let window = web_sys::window().unwrap();
+                let origin = window.location().origin().unwrap_or_default();
+                let link = format!("{}{}_s/{}/", origin, *APP_BASE, share.token);
+                window
+                    .prompt_with_message_and_default("Share link:", &link)
+                    .ok();
+                false
+            }
+            PageMsg::SetCover(path) => {
+                self.batch_task = self.post_cover(&path);
+                false
+            }
+            PageMsg::CoverSet => {
+                self.batch_task = None;
+                web_sys::window()
+                    .unwrap()
+                    .alert_with_message("Folder cover updated")
+                    .ok();
+                false
+            }
+            PageMsg::Reload => {
+                self.reload_task = None;
+                if let Some(loaded) = self.loaded.to_owned() {
+                    self.task = self.fetch_page(&loaded);
+                }
+                false
+            }
+            PageMsg::Libraries(libraries) => {
+                self.libraries_task = None;
+                self.libraries = libraries;
+                true
+            }
         }
     }
 
@@ -137,6 +383,7 @@ impl Component for Page {
                     self.loaded = Some(props.path.to_owned());
                     self.task = self.fetch_page(props.path.as_str());
                     self.show_loading = true;
+                    self.clear_selection();
                 } else {
                     self.show_loading = false;
                 }
@@ -154,24 +401,15 @@ impl Component for Page {
 
     fn rendered(&mut self, first_render: bool) {
         // On page init, the path may be a dir or a file
-        // display modal if it's a file + load directory the file is in
+        // Paths that may be a file are looked up on their own first (see PageMsg::Stat)
         if first_render {
-            let fetch_path: &str;
-            if is_media(&self.props.path.as_str()) {
-                // Trigger modal
-                self.link
-                    .callback(PageMsg::Modal)
-                    .emit(self.props.path.to_owned());
-
-                // Get dir of file
-                let index = self.props.path.rfind('/').unwrap();
-                fetch_path = &self.props.path[0..index + 1];
+            if self.props.path.is_empty() || self.props.path.ends_with('/') {
+                self.loaded = Some(self.props.path.to_owned());
+                self.task = self.fetch_page(&self.props.path);
             } else {
-                fetch_path = &self.props.path;
+                self.stat_task = self.fetch_stat(&self.props.path);
             }
-
-            self.loaded = Some(fetch_path.to_string());
-            self.task = self.fetch_page(fetch_path);
+            self.libraries_task = self.fetch_libraries();
         }
 
         if let Some(data) = &self.props.page {
@@ -188,6 +426,9 @@ impl Component for Page {
             title = data.title.as_str();
             base_path = data.base_path.as_str();
 
+            let onselect = self
+                .link
+                .callback(|(path, range): (String, bool)| PageMsg::Select(path, range));
             let folders = data.folders.iter().map(|e| {
                 html! {
                     <Entry
@@ -199,6 +440,10 @@ impl Component for Page {
                       thumb={ e.thumb.to_owned() }
                       ext={ e.ext.to_owned() }
                       etype="folder"
+                      selecting={ self.selecting }
+                      selected={ self.selected.contains(&e.path) }
+                      onselect={ onselect.to_owned() }
+                      no_download={ !data.download }
                       />
                 }
             });
@@ -213,27 +458,38 @@ impl Component for Page {
                       thumb={ e.thumb.to_owned() }
                       ext={ e.ext.to_owned() }
                       etype="file"
+                      selecting={ self.selecting }
+                      selected={ self.selected.contains(&e.path) }
+                      onselect={ onselect.to_owned() }
+                      no_download={ !data.download }
                       />
                 }
             });
             html! {
+                <>
+                { self.view_toolbar(data) }
                 <div class="row gx-5">
                 { for folders }
                 { for files }
                 </div>
+                </>
             }
         } else {
             html! {}
         };
 
         // Convert title into links for each subdir
+        // The base path (eg, a share link) is a single link of its own
         let combined = if title == String::from("") {
-            base_path.to_string()
+            String::new()
         } else {
-            format!("{}{}/", base_path, title)
+            format!("{}/", title)
         };
         let split = combined.split_inclusive('/').enumerate();
         let clone = split.clone();
+        let base_link = html! {
+            <AppAnchor route={ App::route(base_path) }>{ base_path }</AppAnchor>
+        };
         let html_title = split.map(|s| {
             // Note: Not happy with a loop of "clone" calls
             // but the strings have to be duplicated anyway.
@@ -245,10 +501,30 @@ impl Component for Page {
                 .collect::<String>();
             let text = &s.1;
             html! {
-                <AppAnchor route={ AppRoute::Entry(link) }>{ text }</AppAnchor>
+                <AppAnchor route={ App::route(&format!("{}{}", base_path, link)) }>{ text }</AppAnchor>
             }
         });
 
+        // Disk usage of the folder, not for share links nor the list of libraries
+        // and duplicate files
+        let usage = match &self.props.page {
+            Some(data) if !data.shared && !(title.is_empty() && !self.libraries.is_empty()) => {
+                let usage = App::route(&format!("/_usage/{}", title));
+                let dupes = App::route(&format!("/_dupes/{}", title));
+                html! {
+                    <>
+                    <AppAnchor classes="usage-link" route={ usage }>
+                        <i class="bi bi-pie-chart" title="Disk usage"></i>
+                    </AppAnchor>
+                    <AppAnchor classes="usage-link" route={ dupes }>
+                        <i class="bi bi-files" title="Duplicate files"></i>
+                    </AppAnchor>
+                    </>
+                }
+            }
+            _ => html! {},
+        };
+
         let loading = if self.show_loading {
             html! {<span class="loading"></span>}
         } else {
@@ -261,11 +537,19 @@ impl Component for Page {
             html! {}
         };
 
+        // Covers are changed from the modal, when the folder can be changed
+        let editable = matches!(&self.props.page, Some(p) if !p.read_only && !p.shared);
+        // Shares only give access to their folder
+        let similar = matches!(&self.props.page, Some(p) if !p.shared);
+
         html! {
             <>
-                <Modal src={ self.modal.src.to_owned() } media={ self.modal.media.to_owned() } />
+                <Modal src={ self.modal.src.to_owned() } media={ self.modal.media.to_owned() } stat={ self.modal.stat.to_owned() } editable={ editable } similar={ similar } />
+                { self.view_libraries() }
                 <h1 id="title">
+                    { base_link }
                     { for html_title }
+                    { usage }
                     { loading }
                 </h1>
                 { error }
@@ -277,42 +561,346 @@ impl Component for Page {
 
 impl Page {
     fn fetch_page(&self, path: &str) -> Option<FetchTask> {
-        // TODO: This results in double "//" in path.
-        // Not a major issue, but should be accounted for
-        let url = format!("{}{}", *SERVER_URL, path);
+        let url = format!("{}{}", *SERVER_URL, path.trim_start_matches('/'));
+        // Files are only described here, their content is opened by PageMsg::Stat
+        // Listings are revalidated by the browser (ETag), unchanged ones come back as a 304
+        let request = Request::get(url.as_str())
+            .header("Accept", "application/json")
+            .body(Nothing)
+            .expect("Could not load from API");
+        let callback = self
+            .link
+            .callback(|response: Response<Text>| {
+                let status = response.status();
+                if status.as_u16() == 401 {
+                    return PageMsg::Unauthorized;
+                }
+                let body = response.into_body();
+                if !status.is_success() {
+                    return PageMsg::Error(response_error(status, body));
+                }
+
+                let Json(data): Json<Result<Listing, Error>> = Json::from(body);
+                match data {
+                    Ok(Listing::Dir(dir)) => PageMsg::Page(dir),
+                    Ok(Listing::File(stat)) => PageMsg::Stat(stat),
+                    Err(err) => PageMsg::Error(err),
+                }
+            });
+        let task = FetchService::fetch(request, callback).expect("Could not load page");
+        Some(task)
+    }
+
+    fn fetch_stat(&self, path: &str) -> Option<FetchTask> {
+        let url = format!("{}_api/stat/{}", *SERVER_URL, path.trim_start_matches('/'));
         let request = Request::get(url.as_str())
             .body(Nothing)
             .expect("Could not load from API");
         let callback = self
             .link
-            .callback(|response: Response<Json<Result<Dir, Error>>>| {
+            .callback(|response: Response<Text>| {
                 let status = response.status();
+                if status.as_u16() == 401 {
+                    return PageMsg::Unauthorized;
+                }
+                let body = response.into_body();
                 if !status.is_success() {
-                    let err = anyhow!(
-                        "Error: {} ({})",
-                        &status.canonical_reason().unwrap(),
-                        &status.as_str()
-                    );
-                    return PageMsg::Error(err);
+                    return PageMsg::Error(response_error(status, body));
                 }
 
-                let content = response.headers().get("content-type");
-                if content.is_none() {
-                    return PageMsg::Error(anyhow!("Invalid Content Type"));
-                } else if content.unwrap() != &"application/json" {
-                    return PageMsg::File;
+                let Json(data): Json<Result<FileStat, Error>> = Json::from(body);
+                match data {
+                    Ok(stat) => PageMsg::Stat(stat),
+                    Err(err) => PageMsg::Error(err),
                 }
+            });
+        let task = FetchService::fetch(request, callback).expect("Could not load file");
+        Some(task)
+    }
+
+    fn post_batch(&self, action: &str, body: Text) -> Option<FetchTask> {
+        let url = format!("{}_api/batch/{}", *SERVER_URL, action);
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(body)
+            .expect("Could not build batch request");
+        let callback =
+            self.link
+                .callback(|response: Response<Text>| {
+                    let status = response.status();
+                    if status.as_u16() == 401 {
+                        return PageMsg::Unauthorized;
+                    }
+                    let body = response.into_body();
+                    if !status.is_success() {
+                        return PageMsg::Error(response_error(status, body));
+                    }
+
+                    let Json(data): Json<Result<BatchResult, Error>> = Json::from(body);
+                    match data {
+                        Ok(result) => PageMsg::BulkDone(result),
+                        Err(err) => PageMsg::Error(err),
+                    }
+                });
+        let task = FetchService::fetch(request, callback).expect("Could not send batch request");
+        Some(task)
+    }
 
-                let Json(data) = response.into_body();
+    // Ask for the link settings and create a share link for the current folder
+    fn create_share(&self) -> Option<FetchTask> {
+        let window = web_sys::window().unwrap();
+        let path = self.props.page.as_ref()?.title.to_owned();
+
+        let days = window
+            .prompt_with_message_and_default("Link expires after (days, empty for never):", "7")
+            .ok()??;
+        let expires_in = match days.trim() {
+            "" => None,
+            days => Some(days.parse::<u64>().ok()? * 24 * 60 * 60),
+        };
+        let password = window
+            .prompt_with_message("Password (empty for none):")
+            .ok()??;
+        let download = window
+            .confirm_with_message("Allow downloads? (Cancel to only allow viewing)")
+            .unwrap_or(true);
+
+        let body = ShareRequest {
+            path,
+            expires_in,
+            password: Some(password).filter(|p| !p.is_empty()),
+            download,
+        };
+        let url = format!("{}_api/shares", *SERVER_URL);
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(Json(&body))
+            .expect("Could not build share request");
+        let callback = self
+            .link
+            .callback(|response: Response<Text>| {
+                let status = response.status();
+                if status.as_u16() == 401 {
+                    return PageMsg::Unauthorized;
+                }
+                let body = response.into_body();
+                if !status.is_success() {
+                    return PageMsg::Error(response_error(status, body));
+                }
+
+                let Json(data): Json<Result<ShareInfo, Error>> = Json::from(body);
                 match data {
-                    Ok(dir) => PageMsg::Page(dir),
+                    Ok(share) => PageMsg::Shared(share),
                     Err(err) => PageMsg::Error(err),
                 }
             });
-        let task = FetchService::fetch(request, callback).expect("Could not load page");
+        let task = FetchService::fetch(request, callback).expect("Could not create share");
+        Some(task)
+    }
+
+    // Use the thumbnail of `path` as the cover of its folder
+    fn post_cover(&self, path: &str) -> Option<FetchTask> {
+        let body = CoverRequest {
+            path: path.trim_start_matches('/'),
+        };
+        let url = format!("{}_api/cover", *SERVER_URL);
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(Json(&body))
+            .expect("Could not build cover request");
+        let callback = self
+            .link
+            .callback(|response: Response<Text>| {
+                let status = response.status();
+                if status.as_u16() == 401 {
+                    return PageMsg::Unauthorized;
+                }
+                if !status.is_success() {
+                    return PageMsg::Error(response_error(status, response.into_body()));
+                }
+                PageMsg::CoverSet
+            });
+        let task = FetchService::fetch(request, callback).expect("Could not set cover");
         Some(task)
     }
 
+    // Named libraries, the picker is left out with a single root
+    fn fetch_libraries(&self) -> Option<FetchTask> {
+        let url = format!("{}_api/libraries", *SERVER_URL);
+        let request = Request::get(url.as_str())
+            .body(Nothing)
+            .expect("Could not load from API");
+        let callback =
+            self.link
+                .callback(|response: Response<Json<Result<Vec<Library>, Error>>>| {
+                    // Errors (eg, not logged in yet) are reported by the page itself
+                    let Json(data) = response.into_body();
+                    PageMsg::Libraries(data.unwrap_or_default())
+                });
+        FetchService::fetch(request, callback).ok()
+    }
+
+    fn view_libraries(&self) -> Html {
+        let shared = self.props.page.as_ref().map(|p| p.shared).unwrap_or(false);
+        if self.libraries.len() < 2 || shared {
+            return html! {};
+        }
+
+        let current = self.props.path.trim_start_matches('/').split('/').next();
+        let libraries = self.libraries.iter().map(|l| {
+            let class = if current == Some(l.name.as_str()) {
+                "btn btn-sm btn-light"
+            } else {
+                "btn btn-sm btn-outline-light"
+            };
+            html! {
+                <AppAnchor classes={ class } route={ App::route(&format!("/{}/", l.name)) }>
+                    <i class="bi bi-collection"></i>{ " " }{ &l.name }
+                </AppAnchor>
+            }
+        });
+
+        html! {
+            <nav id="libraries" class="btn-group mb-3">
+                { for libraries }
+            </nav>
+        }
+    }
+
+    // Downloads go through a temporary <form> so the browser saves the archive
+    fn submit_download(&self) {
+        let paths: Text = Json(&self.selected).into();
+        let document = web_sys::window().unwrap().document().unwrap();
+        let form = document
+            .create_element("form")
+            .unwrap()
+            .dyn_into::<web_sys::HtmlFormElement>()
+            .unwrap();
+        form.set_method("post");
+        form.set_action(&format!("{}_api/batch/download", *SERVER_URL));
+
+        let input = document.create_element("input").unwrap();
+        input.set_attribute("type", "hidden").unwrap();
+        input.set_attribute("name", "paths").unwrap();
+        input.set_attribute("value", &paths.unwrap()).unwrap();
+        form.append_child(&input).unwrap();
+
+        let body = document.body().unwrap();
+        body.append_child(&form).unwrap();
+        form.submit().unwrap();
+        body.remove_child(&form).unwrap();
+    }
+
+    // Paths of all entries in display order
+    fn entry_paths(&self) -> Vec<String> {
+        if let Some(data) = &self.props.page {
+            data.folders
+                .iter()
+                .chain(data.files.iter())
+                .map(|e| e.path.to_owned())
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.selected.clear();
+        self.anchor = None;
+    }
+
+    // Toggle a single entry, or with `range` select everything
+    // between the previously clicked entry and this one
+    fn select(&mut self, path: String, range: bool) {
+        let paths = self.entry_paths();
+        let index = paths.iter().position(|p| p == &path);
+        let anchor = self
+            .anchor
+            .as_ref()
+            .and_then(|a| paths.iter().position(|p| p == a));
+
+        match (range, anchor, index) {
+            (true, Some(start), Some(end)) => {
+                let (start, end) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                for p in &paths[start..=end] {
+                    if !self.selected.contains(p) {
+                        self.selected.push(p.to_owned());
+                    }
+                }
+            }
+            _ => {
+                if let Some(pos) = self.selected.iter().position(|p| p == &path) {
+                    self.selected.remove(pos);
+                } else {
+                    self.selected.push(path.to_owned());
+                }
+            }
+        }
+
+        self.anchor = Some(path);
+    }
+
+    fn view_toolbar(&self, data: &Dir) -> Html {
+        // Bulk actions and sharing need an account, so they are left out of share links
+        if data.shared {
+            return html! {};
+        }
+
+        let toggle = self.link.callback(|_| PageMsg::ToggleSelecting);
+        if !self.selecting {
+            return html! {
+                <nav id="toolbar" class="mb-4">
+                    <button class="btn btn-sm btn-outline-light" onclick={ toggle }>
+                        <i class="bi bi-check2-square"></i>{ " Select" }
+                    </button>
+                    <button class="btn btn-sm btn-outline-light" disabled={ self.batch_task.is_some() }
+                        onclick={ self.link.callback(|_| PageMsg::Share) }>
+                        <i class="bi bi-share"></i>{ " Share" }
+                    </button>
+                </nav>
+            };
+        }
+
+        let none = self.selected.is_empty();
+        let modify = if data.read_only {
+            html! {}
+        } else {
+            html! {
+                <>
+                    <button class="btn btn-sm btn-outline-warning" disabled={ none }
+                        onclick={ self.link.callback(|_| PageMsg::BulkMove) }>
+                        <i class="bi bi-folder-symlink"></i>{ " Move" }
+                    </button>
+                    <button class="btn btn-sm btn-outline-danger" disabled={ none }
+                        onclick={ self.link.callback(|_| PageMsg::BulkDelete) }>
+                        <i class="bi bi-trash"></i>{ " Delete" }
+                    </button>
+                </>
+            }
+        };
+
+        html! {
+            <nav id="toolbar" class="mb-4">
+                <button class="btn btn-sm btn-light" onclick={ toggle }>
+                    <i class="bi bi-x-lg"></i>{ " Done" }
+                </button>
+                <button class="btn btn-sm btn-outline-light" onclick={ self.link.callback(|_| PageMsg::SelectAll) }>
+                    { "All" }
+                </button>
+                <small class="mx-2">{ self.selected.len() }{ " selected" }</small>
+                <button class="btn btn-sm btn-outline-info" disabled={ none }
+                    onclick={ self.link.callback(|_| PageMsg::BulkDownload) }>
+                    <i class="bi bi-download"></i>{ " Download" }
+                </button>
+                { modify }
+            </nav>
+        }
+    }
+
     // Determine the next file in modal sequence
     fn next_file(&self) -> String {
         let findex = &self.modal.src.rfind('/').expect("complete path");
@@ -349,3 +937,11 @@ impl Page {
         }
     }
 }
+
+// Containing folder of a page path, with its trailing '/'
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(index) => &path[..index + 1],
+        None => "/",
+    }
+}
@@ -0,0 +1,350 @@
+use yew::format::{Json, Nothing, Text};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask, Request, Response};
+use yew::services::ConsoleService;
+use yew::Properties;
+
+use super::entry::EntryProps;
+use crate::api::response_error;
+use crate::{App, SERVER_URL};
+use anyhow::Error;
+
+// Comic book archives open in the reader instead of the gallery
+pub const COMIC_TYPES: [&str; 3] = [".cbz", ".cbr", ".cbt"];
+pub fn is_comic(path: &str) -> bool {
+    let p = &path.to_lowercase();
+    COMIC_TYPES.iter().any(|t| p.ends_with(t))
+}
+
+// localStorage keys for reader settings and per-archive reading position
+const SETTINGS_KEY: &str = "hview.reader";
+const POSITION_KEY: &str = "hview.reader.position:";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ReaderSettings {
+    spread: bool,
+    rtl: bool,
+}
+
+pub enum ReaderMsg {
+    Pages(Vec<EntryProps>),
+    Error(Error),
+    Unauthorized,
+    Next,
+    Previous,
+    ToggleSpread,
+    ToggleDirection,
+    Close,
+    None,
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct ReaderProps {
+    pub path: String,
+}
+
+pub struct Reader {
+    link: ComponentLink<Self>,
+    props: ReaderProps,
+    node: NodeRef,
+    task: Option<FetchTask>,
+    error: Option<Error>,
+
+    pages: Vec<EntryProps>,
+    index: usize,
+    spread: bool,
+    rtl: bool,
+}
+
+impl Component for Reader {
+    type Message = ReaderMsg;
+    type Properties = ReaderProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let settings = Self::load_settings();
+        let mut reader = Self {
+            link,
+            props,
+            node: NodeRef::default(),
+            task: None,
+            error: None,
+            pages: Vec::new(),
+            index: 0,
+            spread: settings.spread,
+            rtl: settings.rtl,
+        };
+        reader.task = reader.fetch_pages();
+        reader
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            ReaderMsg::Pages(pages) => {
+                self.pages = pages;
+                self.index = self.load_position().min(self.pages.len().saturating_sub(1));
+                self.error = None;
+                self.task = None;
+                true
+            }
+            ReaderMsg::Unauthorized => {
+                App::replace_route(format!("/_login/_read/{}", &self.props.path));
+                false
+            }
+            ReaderMsg::Error(error) => {
+                ConsoleService::error(format!("Invalid response: {:?}", error).as_str());
+                self.error = Some(error);
+                self.task = None;
+                true
+            }
+            ReaderMsg::Next => self.go_to(self.next_index()),
+            ReaderMsg::Previous => self.go_to(self.prev_index()),
+            ReaderMsg::ToggleSpread => {
+                self.spread = !self.spread;
+                self.save_settings();
+                true
+            }
+            ReaderMsg::ToggleDirection => {
+                self.rtl = !self.rtl;
+                self.save_settings();
+                true
+            }
+            ReaderMsg::Close => {
+                // Return to the folder containing the archive
+                let path = self.props.path.trim_end_matches('/');
+                if let Some(index) = path.rfind('/') {
+                    App::change_route(format!("/{}/", &path[0..index]));
+                } else {
+                    App::change_route(String::from("/"));
+                }
+                false
+            }
+            ReaderMsg::None => false,
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            self.props = props;
+            self.pages.clear();
+            self.index = 0;
+            self.task = self.fetch_pages();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let p = &self.props;
+        let name = p.path.rsplit('/').next().unwrap_or("");
+
+        // Arrow keys follow the reading direction
+        let rtl = self.rtl;
+        let onkeydown =
+            self.link
+                .callback(move |event: KeyboardEvent| match event.key().as_str() {
+                    "ArrowRight" if rtl => ReaderMsg::Previous,
+                    "ArrowRight" => ReaderMsg::Next,
+                    "ArrowLeft" if rtl => ReaderMsg::Next,
+                    "ArrowLeft" => ReaderMsg::Previous,
+                    " " => ReaderMsg::Next,
+                    "Backspace" => ReaderMsg::Previous,
+                    "Escape" => ReaderMsg::Close,
+                    _ => ReaderMsg::None,
+                });
+
+        // Clicking the left or right side of the page turns it
+        let onclick = self.link.callback(move |event: MouseEvent| {
+            let width = web_sys::window()
+                .unwrap()
+                .inner_width()
+                .ok()
+                .and_then(|w| w.as_f64())
+                .unwrap_or(0.0);
+            let left = (event.client_x() as f64) < width / 2.0;
+            if left != rtl {
+                ReaderMsg::Previous
+            } else {
+                ReaderMsg::Next
+            }
+        });
+
+        let pages = self.visible_pages().into_iter().map(|i| {
+            let src = format!("{}{}", *SERVER_URL, &self.pages[i].path);
+            html! { <img src={ src } alt={ self.pages[i].name.to_owned() } /> }
+        });
+
+        let content = if let Some(error) = &self.error {
+            html! {<h2 class="text-danger">{ "Error: " }{ error }</h2>}
+        } else if self.task.is_some() {
+            html! {<span class="loading"></span>}
+        } else if self.pages.is_empty() {
+            html! {<h2>{ "No pages found" }</h2>}
+        } else {
+            let direction = if self.rtl { "rtl" } else { "ltr" };
+            html! {
+                <div class=classes!("pages", direction) onclick={ onclick }>
+                    { for pages }
+                </div>
+            }
+        };
+
+        let position = if self.pages.is_empty() {
+            String::new()
+        } else {
+            format!("{} / {}", self.index + 1, self.pages.len())
+        };
+
+        html! {
+            <div id="reader" tabindex="0" ref={ self.node.clone() } onkeydown={ onkeydown }>
+                <nav class="mb-2">
+                    <button class="btn btn-sm btn-outline-light" onclick={ self.link.callback(|_| ReaderMsg::Close) }>
+                        <i class="bi bi-x-lg"></i>
+                    </button>
+                    <strong class="mx-2">{ name }</strong>
+                    <small class="mx-2">{ position }</small>
+                    <button class="btn btn-sm btn-outline-light" onclick={ self.link.callback(|_| ReaderMsg::ToggleSpread) }>
+                        { if self.spread { "Double page" } else { "Single page" } }
+                    </button>
+                    <button class="btn btn-sm btn-outline-light ms-2" onclick={ self.link.callback(|_| ReaderMsg::ToggleDirection) }>
+                        { if self.rtl { "Right to left" } else { "Left to right" } }
+                    </button>
+                </nav>
+                { content }
+            </div>
+        }
+    }
+
+    fn rendered(&mut self, first_render: bool) {
+        // Focus reader so keyboard navigation works right away
+        if first_render {
+            if let Some(element) = self.node.cast::<web_sys::HtmlElement>() {
+                element.focus().ok();
+            }
+        }
+
+        App::set_title(self.props.path.to_string());
+    }
+}
+
+impl Reader {
+    fn fetch_pages(&self) -> Option<FetchTask> {
+        let url = format!("{}_api/pages/{}", *SERVER_URL, &self.props.path);
+        let request = Request::get(url.as_str())
+            .body(Nothing)
+            .expect("Could not load from API");
+        let callback =
+            self.link
+                .callback(|response: Response<Text>| {
+                    let status = response.status();
+                    if status.as_u16() == 401 {
+                        return ReaderMsg::Unauthorized;
+                    }
+                    let body = response.into_body();
+                    if !status.is_success() {
+                        return ReaderMsg::Error(response_error(status, body));
+                    }
+
+                    let Json(data): Json<Result<Vec<EntryProps>, Error>> = Json::from(body);
+                    match data {
+                        Ok(pages) => ReaderMsg::Pages(pages),
+                        Err(err) => ReaderMsg::Error(err),
+                    }
+                });
+        let task = FetchService::fetch(request, callback).expect("Could not load pages");
+        Some(task)
+    }
+
+    // Pages shown at the current position
+    // In double page mode the cover is shown on its own, followed by pairs
+    fn visible_pages(&self) -> Vec<usize> {
+        if self.pages.is_empty() {
+            return Vec::new();
+        }
+
+        let mut pages = vec![self.index];
+        if self.spread && self.index > 0 && self.index + 1 < self.pages.len() {
+            pages.push(self.index + 1);
+        }
+        if self.rtl {
+            pages.reverse();
+        }
+        pages
+    }
+
+    fn next_index(&self) -> usize {
+        let step = if self.spread && self.index > 0 { 2 } else { 1 };
+        let next = self.index + step;
+        if next < self.pages.len() {
+            next
+        } else {
+            self.index
+        }
+    }
+
+    fn prev_index(&self) -> usize {
+        if self.spread && self.index > 1 {
+            // Keep pairs aligned to odd pages (1-2, 3-4, ...)
+            (self.index - 2) | 1
+        } else {
+            self.index.saturating_sub(1)
+        }
+    }
+
+    fn go_to(&mut self, index: usize) -> ShouldRender {
+        if index == self.index {
+            return false;
+        }
+
+        self.index = index;
+        self.save_position();
+        true
+    }
+
+    fn storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    fn load_settings() -> ReaderSettings {
+        let stored = Self::storage().and_then(|s| s.get_item(SETTINGS_KEY).ok()?);
+        let parse = |value: &str| -> Option<ReaderSettings> {
+            let mut parts = value.split(',');
+            Some(ReaderSettings {
+                spread: parts.next()? == "spread",
+                rtl: parts.next()? == "rtl",
+            })
+        };
+        stored
+            .and_then(|value| parse(&value))
+            .unwrap_or(ReaderSettings {
+                spread: false,
+                rtl: false,
+            })
+    }
+
+    fn save_settings(&self) {
+        let value = format!(
+            "{},{}",
+            if self.spread { "spread" } else { "single" },
+            if self.rtl { "rtl" } else { "ltr" }
+        );
+        if let Some(storage) = Self::storage() {
+            storage.set_item(SETTINGS_KEY, &value).ok();
+        }
+    }
+
+    fn load_position(&self) -> usize {
+        let key = format!("{}{}", POSITION_KEY, &self.props.path);
+        Self::storage()
+            .and_then(|s| s.get_item(&key).ok()?)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    }
+
+    fn save_position(&self) {
+        let key = format!("{}{}", POSITION_KEY, &self.props.path);
+        if let Some(storage) = Self::storage() {
+            storage.set_item(&key, &self.index.to_string()).ok();
+        }
+    }
+}
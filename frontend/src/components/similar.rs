@@ -0,0 +1,212 @@
+use serde::Deserialize;
+use std::time::Duration;
+use yew::format::{Json, Nothing, Text};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask, Request, Response};
+use yew::services::timeout::{TimeoutService, TimeoutTask};
+use yew::services::ConsoleService;
+use yew::Properties;
+
+use super::entry::EntryProps;
+use crate::api::response_error;
+use crate::{App, AppAnchor, SERVER_URL};
+use anyhow::Error;
+
+// An image alike the one asked about
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SimilarImage {
+    // Differing bits of the hashes (out of 64)
+    distance: u32,
+    file: EntryProps,
+}
+
+// Progress of the index, and the closest images once done
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SimilarReport {
+    done: bool,
+    files: u64,
+    #[serde(default)]
+    similar: Vec<SimilarImage>,
+}
+
+pub enum SimilarMsg {
+    Report(SimilarReport),
+    Poll,
+    Refresh,
+    Error(Error),
+    Unauthorized,
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct SimilarProps {
+    pub path: String,
+}
+
+// Images of the library that look like one, indexed in the background by the backend
+pub struct Similar {
+    link: ComponentLink<Self>,
+    props: SimilarProps,
+    task: Option<FetchTask>,
+    poll_task: Option<TimeoutTask>,
+    report: Option<SimilarReport>,
+    error: Option<Error>,
+}
+
+impl Component for Similar {
+    type Message = SimilarMsg;
+    type Properties = SimilarProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut similar = Self {
+            link,
+            props,
+            task: None,
+            poll_task: None,
+            report: None,
+            error: None,
+        };
+        similar.task = similar.fetch_similar(false);
+        similar
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            SimilarMsg::Report(report) => {
+                self.task = None;
+                self.error = None;
+                // Ask again until the index is done
+                self.poll_task = if report.done {
+                    None
+                } else {
+                    let poll = self.link.callback(|_| SimilarMsg::Poll);
+                    Some(TimeoutService::spawn(Duration::from_secs(1), poll))
+                };
+                self.report = Some(report);
+                true
+            }
+            SimilarMsg::Poll => {
+                self.task = self.fetch_similar(false);
+                false
+            }
+            SimilarMsg::Refresh => {
+                self.report = None;
+                self.task = self.fetch_similar(true);
+                true
+            }
+            SimilarMsg::Unauthorized => {
+                App::replace_route(format!("/_login/_similar/{}", &self.props.path));
+                false
+            }
+            SimilarMsg::Error(error) => {
+                ConsoleService::error(format!("Invalid response: {:?}", error).as_str());
+                self.error = Some(error);
+                self.task = None;
+                self.poll_task = None;
+                true
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            self.props = props;
+            self.report = None;
+            self.poll_task = None;
+            self.task = self.fetch_similar(false);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let path = self.props.path.trim_matches('/');
+        App::set_title(format!("Similar to: /{}", path));
+
+        let content = match (&self.error, &self.report) {
+            (Some(error), _) => html! {<h2 class="text-danger">{ "Error: " }{ error }</h2>},
+            (None, Some(report)) if report.done && report.similar.is_empty() => {
+                html! {<p>{ "No similar images found" }</p>}
+            }
+            (None, Some(report)) if report.done => html! {
+                <div class="similar">
+                    { for report.similar.iter().map(|image| self.view_image(image)) }
+                </div>
+            },
+            (None, Some(report)) => html! {
+                <p>
+                    <span class="loading"></span>
+                    { "Indexing: " }{ report.files }{ " images" }
+                </p>
+            },
+            (None, None) => html! {<span class="loading"></span>},
+        };
+
+        let original = format!("{}{}", *SERVER_URL, path);
+        html! {
+            <>
+                <h1 id="title">
+                    <AppAnchor route={ App::route(&format!("/{}", path)) }>{ "/" }{ path }</AppAnchor>
+                    <button class="btn btn-sm btn-outline-light ms-2" disabled={ self.task.is_some() || self.poll_task.is_some() }
+                        onclick={ self.link.callback(|_| SimilarMsg::Refresh) }>
+                        { "Index again" }
+                    </button>
+                </h1>
+                <img src={ original } class="similar-original mb-3" />
+                { content }
+            </>
+        }
+    }
+}
+
+impl Similar {
+    fn view_image(&self, image: &SimilarImage) -> Html {
+        let file = &image.file;
+        let thumb = match &file.thumb {
+            Some(thumb) => {
+                let src = format!("{}{}", *SERVER_URL, thumb);
+                html! {<img src={ src } loading="lazy" class="similar-thumb" />}
+            }
+            None => html! {<i class="bi-image similar-thumb"></i>},
+        };
+        let title = format!("{} ({} bits apart)", file.path, image.distance);
+        html! {
+            <AppAnchor route={ App::route(&file.path) } classes="similar-image">
+                <div title={ title }>
+                    { thumb }
+                    <small>{ &file.name }<span class="badge bg-secondary ms-1">{ image.distance }</span></small>
+                </div>
+            </AppAnchor>
+        }
+    }
+
+    fn fetch_similar(&self, refresh: bool) -> Option<FetchTask> {
+        let url = format!(
+            "{}_api/similar/{}?refresh={}",
+            *SERVER_URL,
+            self.props.path.trim_matches('/'),
+            refresh
+        );
+        let request = Request::get(url.as_str())
+            .body(Nothing)
+            .expect("Could not load from API");
+        let callback = self.link.callback(|response: Response<Text>| {
+            let status = response.status();
+            if status.as_u16() == 401 {
+                return SimilarMsg::Unauthorized;
+            }
+            let body = response.into_body();
+            if !status.is_success() {
+                return SimilarMsg::Error(response_error(status, body));
+            }
+
+            let Json(data): Json<Result<SimilarReport, Error>> = Json::from(body);
+            match data {
+                Ok(report) => SimilarMsg::Report(report),
+                Err(err) => SimilarMsg::Error(err),
+            }
+        });
+        let task = FetchService::fetch(request, callback).expect("Could not load similar images");
+        Some(task)
+    }
+}
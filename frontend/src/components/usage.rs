@@ -0,0 +1,283 @@
+use serde::Deserialize;
+use std::time::Duration;
+use yew::format::{Json, Nothing, Text};
+use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask, Request, Response};
+use yew::services::timeout::{TimeoutService, TimeoutTask};
+use yew::services::ConsoleService;
+use yew::Properties;
+
+use crate::api::response_error;
+use crate::{App, AppAnchor, SERVER_URL};
+use anyhow::Error;
+
+// Levels and children per folder shown in the treemap
+const DEPTH: usize = 3;
+const TOP: usize = 12;
+
+// A file or folder with the space it takes, largest children first
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UsageNode {
+    name: String,
+    path: String,
+    folder: bool,
+    bytes: u64,
+    files: u64,
+    // Children left out (too small, or not readable), added up
+    other_bytes: u64,
+    other_files: u64,
+    children: Vec<UsageNode>,
+}
+
+// Progress of the scan, and its tree once done
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UsageReport {
+    done: bool,
+    files: u64,
+    bytes: u64,
+    tree: Option<UsageNode>,
+}
+
+pub enum UsageMsg {
+    Report(UsageReport),
+    Poll,
+    Refresh,
+    Error(Error),
+    Unauthorized,
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct UsageProps {
+    pub path: String,
+}
+
+// Treemap of the space taken by a folder, scanned in the background by the backend
+pub struct Usage {
+    link: ComponentLink<Self>,
+    props: UsageProps,
+    task: Option<FetchTask>,
+    poll_task: Option<TimeoutTask>,
+    report: Option<UsageReport>,
+    error: Option<Error>,
+}
+
+impl Component for Usage {
+    type Message = UsageMsg;
+    type Properties = UsageProps;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut usage = Self {
+            link,
+            props,
+            task: None,
+            poll_task: None,
+            report: None,
+            error: None,
+        };
+        usage.task = usage.fetch_usage(false);
+        usage
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            UsageMsg::Report(report) => {
+                self.task = None;
+                self.error = None;
+                // Ask again until the scan is done
+                self.poll_task = if report.done {
+                    None
+                } else {
+                    let poll = self.link.callback(|_| UsageMsg::Poll);
+                    Some(TimeoutService::spawn(Duration::from_secs(1), poll))
+                };
+                self.report = Some(report);
+                true
+            }
+            UsageMsg::Poll => {
+                self.task = self.fetch_usage(false);
+                false
+            }
+            UsageMsg::Refresh => {
+                self.report = None;
+                self.task = self.fetch_usage(true);
+                true
+            }
+            UsageMsg::Unauthorized => {
+                App::replace_route(format!("/_login/_usage/{}", &self.props.path));
+                false
+            }
+            UsageMsg::Error(error) => {
+                ConsoleService::error(format!("Invalid response: {:?}", error).as_str());
+                self.error = Some(error);
+                self.task = None;
+                self.poll_task = None;
+                true
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        if self.props != props {
+            self.props = props;
+            self.report = None;
+            self.poll_task = None;
+            self.task = self.fetch_usage(false);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn view(&self) -> Html {
+        let path = self.props.path.trim_matches('/');
+        App::set_title(format!("Disk usage: /{}", path));
+
+        let content = match (&self.error, &self.report) {
+            (Some(error), _) => html! {<h2 class="text-danger">{ "Error: " }{ error }</h2>},
+            (
+                None,
+                Some(UsageReport {
+                    tree: Some(tree), ..
+                }),
+            ) => html! {
+                <>
+                    <p>{ human_size(tree.bytes) }{ " in " }{ tree.files }{ " files" }</p>
+                    <div id="usage">{ self.view_children(tree, 0) }</div>
+                </>
+            },
+            (None, Some(report)) => html! {
+                <p>
+                    <span class="loading"></span>
+                    { "Scanning: " }{ report.files }{ " files, " }{ human_size(report.bytes) }
+                </p>
+            },
+            (None, None) => html! {<span class="loading"></span>},
+        };
+
+        html! {
+            <>
+                <h1 id="title">
+                    <AppAnchor route={ App::route(&format!("/{}/", path)) }>{ "/" }{ path }</AppAnchor>
+                    <button class="btn btn-sm btn-outline-light ms-2" disabled={ self.task.is_some() || self.poll_task.is_some() }
+                        onclick={ self.link.callback(|_| UsageMsg::Refresh) }>
+                        { "Scan again" }
+                    </button>
+                </h1>
+                { content }
+            </>
+        }
+    }
+}
+
+impl Usage {
+    fn fetch_usage(&self, refresh: bool) -> Option<FetchTask> {
+        let url = format!(
+            "{}_api/usage/{}?depth={}&top={}&refresh={}",
+            *SERVER_URL,
+            self.props.path.trim_matches('/'),
+            DEPTH,
+            TOP,
+            refresh
+        );
+        let request = Request::get(url.as_str())
+            .body(Nothing)
+            .expect("Could not load from API");
+        let callback = self.link.callback(|response: Response<Text>| {
+            let status = response.status();
+            if status.as_u16() == 401 {
+                return UsageMsg::Unauthorized;
+            }
+            let body = response.into_body();
+            if !status.is_success() {
+                return UsageMsg::Error(response_error(status, body));
+            }
+
+            let Json(data): Json<Result<UsageReport, Error>> = Json::from(body);
+            match data {
+                Ok(report) => UsageMsg::Report(report),
+                Err(err) => UsageMsg::Error(err),
+            }
+        });
+        let task = FetchService::fetch(request, callback).expect("Could not load usage");
+        Some(task)
+    }
+
+    // Children of `node` side by side, sized by the space they take
+    // Levels alternate between rows and columns (slice and dice)
+    fn view_children(&self, node: &UsageNode, depth: usize) -> Html {
+        if node.bytes == 0 {
+            return html! {};
+        }
+
+        let horizontal = depth % 2 == 0;
+        let mut offset = 0.0;
+        let mut place = |bytes: u64| {
+            let share = bytes as f64 * 100.0 / node.bytes as f64;
+            let style = if horizontal {
+                format!("left:{:.3}%;width:{:.3}%;top:0;height:100%", offset, share)
+            } else {
+                format!("top:{:.3}%;height:{:.3}%;left:0;width:100%", offset, share)
+            };
+            offset += share;
+            style
+        };
+
+        let hue = (depth * 47 + 200) % 360;
+        let children: Vec<Html> = node.children.iter().filter(|c| c.bytes > 0).map(|child| {
+            let style = format!("{};background-color:hsl({},35%,{}%)", place(child.bytes), hue, 22 + depth * 6);
+            let title = format!("{} ({}, {} files)", child.path, human_size(child.bytes), child.files);
+            let label = if child.folder {
+                html! {
+                    <AppAnchor route={ App::route(&format!("/_usage/{}", child.path)) }>{ &child.name }</AppAnchor>
+                }
+            } else {
+                html! { <span>{ &child.name }</span> }
+            };
+            html! {
+                <div class="usage-node" style={ style } title={ title }>
+                    <div class="usage-label">{ label }{ " " }<small>{ human_size(child.bytes) }</small></div>
+                    <div class="usage-children">{ self.view_children(child, depth + 1) }</div>
+                </div>
+            }
+        }).collect();
+
+        let other = if node.other_bytes > 0 {
+            let style = format!("{};background-color:#333", place(node.other_bytes));
+            let title = format!(
+                "{} more files ({})",
+                node.other_files,
+                human_size(node.other_bytes)
+            );
+            html! {
+                <div class="usage-node" style={ style } title={ title }>
+                    <div class="usage-label"><small>{ "other" }</small></div>
+                </div>
+            }
+        } else {
+            html! {}
+        };
+
+        html! {
+            <>
+                { for children }
+                { other }
+            </>
+        }
+    }
+}
+
+// Size for people, eg "1.5 GB"
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
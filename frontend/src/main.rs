@@ -1,5 +1,4 @@
 use lazy_static::lazy_static;
-use std::env;
 use yew::prelude::*;
 use yew::services::ConsoleService;
 use yew_router::components::RouterAnchor;
@@ -9,15 +8,45 @@ use yew_router::{
     route::Route,
 };
 
+mod api;
 mod components;
+use crate::components::dupes::Dupes;
+use crate::components::login::Login;
 use crate::components::page::Page;
+use crate::components::reader::Reader;
+use crate::components::similar::Similar;
+use crate::components::usage::Usage;
 
-// TODO: Move this to config?
 lazy_static! {
+    // Path the app is served from, from `<base data-trunk-public-url />`
+    // (set with `trunk build --public-url /sub/`), eg "/" or "/sub/"
+    pub static ref APP_BASE: String = app_base();
+    // Backend API base, from `<meta name="hview-api" content="...">`,
+    // "api/" under the app base when missing
     pub static ref SERVER_URL: String =
-        env::var("TRUNK_PROXY_REWRITE").unwrap_or(String::from("/api/"));
+        meta_content("hview-api").unwrap_or_else(|| format!("{}api/", *APP_BASE));
 }
 
+fn app_base() -> String {
+    let base = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.query_selector("base[href]").ok().flatten())
+        .and_then(|b| b.get_attribute("href"))
+        .and_then(|href| web_sys::Url::new(&href).ok().map(|u| u.pathname()).or(Some(href)))
+        .unwrap_or_else(|| String::from("/"));
+    format!("/{}/", base.trim_matches('/')).replace("//", "/")
+}
+
+fn meta_content(name: &str) -> Option<String> {
+    web_sys::window()?
+        .document()?
+        .query_selector(&format!("meta[name=\"{}\"]", name))
+        .ok()??
+        .get_attribute("content")
+        .filter(|c| !c.is_empty())
+}
+
+// Every location, including the app base (see App::route)
 #[derive(Switch, Clone)]
 pub enum AppRoute {
     #[to = "{*}"]
@@ -45,11 +74,26 @@ impl Component for App {
 
     fn view(&self) -> Html {
         let render = Router::render(move |switch: AppRoute| -> Html {
-            match switch {
-                AppRoute::Entry(path) => {
-                    ConsoleService::info("Loading Path");
-                    html! { <Page path={ path } /> }
-                }
+            let AppRoute::Entry(location) = switch;
+            let path = App::app_path(&location);
+            if let Some(next) = path.strip_prefix("/_login") {
+                ConsoleService::info("Loading Login");
+                html! { <Login next={ next.to_string() } /> }
+            } else if let Some(path) = path.strip_prefix("/_read/") {
+                ConsoleService::info("Loading Reader");
+                html! { <Reader path={ path.to_string() } /> }
+            } else if let Some(path) = path.strip_prefix("/_usage/") {
+                ConsoleService::info("Loading Usage");
+                html! { <Usage path={ path.to_string() } /> }
+            } else if let Some(path) = path.strip_prefix("/_dupes/") {
+                ConsoleService::info("Loading Duplicates");
+                html! { <Dupes path={ path.to_string() } /> }
+            } else if let Some(path) = path.strip_prefix("/_similar/") {
+                ConsoleService::info("Loading Similar");
+                html! { <Similar path={ path.to_string() } /> }
+            } else {
+                ConsoleService::info("Loading Path");
+                html! { <Page path={ path } /> }
             }
         });
 
@@ -62,17 +106,24 @@ impl Component for App {
 }
 
 impl App {
+    // Route to an app path ("/photos/"), under the app base
+    pub fn route(path: &str) -> AppRoute {
+        AppRoute::Entry(format!("{}{}", *APP_BASE, path.trim_start_matches('/')))
+    }
+    // App path of a location, without the app base
+    pub fn app_path(location: &str) -> String {
+        let path = location
+            .strip_prefix(APP_BASE.trim_end_matches('/'))
+            .unwrap_or(location);
+        format!("/{}", path.trim_start_matches('/'))
+    }
     pub fn change_route(src: String) {
-        RouteAgent::dispatcher().send(RouteRequest::ChangeRoute(Route {
-            route: src,
-            state: (),
-        }));
+        let AppRoute::Entry(route) = Self::route(&src);
+        RouteAgent::dispatcher().send(RouteRequest::ChangeRoute(Route { route, state: () }));
     }
     pub fn replace_route(src: String) {
-        RouteAgent::dispatcher().send(RouteRequest::ReplaceRoute(Route {
-            route: src,
-            state: (),
-        }));
+        let AppRoute::Entry(route) = Self::route(&src);
+        RouteAgent::dispatcher().send(RouteRequest::ReplaceRoute(Route { route, state: () }));
     }
     pub fn set_title(title: String) {
         web_sys::window()